@@ -0,0 +1,68 @@
+//! Drawing several L-systems together in one SVG.
+
+use crate::{write_drawings, LSystem, RenderError, SvgOptions};
+use rust_decimal::Decimal;
+use std::io::Write;
+
+/// Where an L-system is drawn by [`render_many`], in the units of [`LSystem::step_lengths`].
+///
+/// Points are scaled about the L-system's own origin before they are translated.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(default))]
+pub struct Placement {
+    /// Distance to move the drawing by.
+    pub translation: (Decimal, Decimal),
+    /// Factor to multiply the size of the drawing by.
+    pub scale: Decimal,
+}
+
+impl Default for Placement {
+    /// Leaves the drawing where it is.
+    fn default() -> Self {
+        Self {
+            translation: (Decimal::ZERO, Decimal::ZERO),
+            scale: Decimal::ONE,
+        }
+    }
+}
+
+impl Placement {
+    fn place(&self, (x, y): (Decimal, Decimal)) -> (Decimal, Decimal) {
+        (
+            x * self.scale + self.translation.0,
+            y * self.scale + self.translation.1,
+        )
+    }
+}
+
+/// Run each L-system and draw them all into one SVG, each in its own `<g>`.
+///
+/// The L-systems are laid out by their [`Placement`] and then scaled together to fit the canvas
+/// like [`LSystem::to_svg`] scales one, so their sizes stay relative to each other.
+pub fn render_many<A, R, W>(
+    systems: &[(LSystem<A, R>, Placement)],
+    options: &SvgOptions,
+    writer: W,
+) -> Result<(), RenderError>
+where
+    A: AsRef<str>,
+    R: AsRef<str>,
+    W: Write,
+{
+    let turtles = systems
+        .iter()
+        .map(|(l_system, placement)| {
+            let mut turtle = l_system.walk_turtle(l_system.checked_symbols()?)?;
+            for stroke in turtle.strokes.iter_mut().chain(&mut turtle.dots) {
+                stroke.start = placement.place(stroke.start);
+                stroke.end = placement.place(stroke.end);
+            }
+            for point in &mut turtle.leaves {
+                *point = placement.place(*point);
+            }
+            Ok(turtle)
+        })
+        .collect::<Result<Vec<_>, RenderError>>()?;
+    write_drawings(&turtles, true, None, options, writer)
+}
@@ -0,0 +1,140 @@
+//! Curated L-systems shared by the front-ends.
+
+use crate::{parse_rules, LSystem, ParseError};
+
+/// A well-known L-system, described the way a person would type it in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Example {
+    /// Name of the L-system, i.e. `Koch`.
+    pub name: &'static str,
+    /// Initial string.
+    pub axiom: &'static str,
+    /// Variables that should be treated as a stroke and drawn.
+    pub variables_to_draw: &'static str,
+    /// Replacement rules in the format read by [`parse_rules`].
+    pub rules: &'static [&'static str],
+    /// Turn angle in degrees.
+    pub angle_degrees: u32,
+    /// Number of times the rules run for a good looking result.
+    pub iterations: usize,
+}
+
+impl Example {
+    /// Convert the example into an [`LSystem`].
+    pub fn l_system(&self) -> Result<LSystem<String, String>, ParseError> {
+        Ok(LSystem {
+            axiom: self.axiom.to_string(),
+            variables_to_draw: self.variables_to_draw.chars().collect(),
+            iterations: self.iterations,
+            rules: parse_rules(&self.rules.join("\n"))?,
+            ..Default::default()
+        }
+        .with_angle_degrees(self.angle_degrees))
+    }
+}
+
+/// All of the curated examples.
+pub fn examples() -> &'static [Example] {
+    EXAMPLES
+}
+
+const EXAMPLES: &[Example] = &[
+    Example {
+        name: "Koch",
+        axiom: "F",
+        variables_to_draw: "F",
+        rules: &["F=>F+F-F-F+F"],
+        angle_degrees: 90,
+        iterations: 4,
+    },
+    Example {
+        name: "Sierpinski Triangle",
+        axiom: "F-G-G",
+        variables_to_draw: "FG",
+        rules: &["F=>F-G+F+G-F", "G=>GG"],
+        angle_degrees: 120,
+        iterations: 6,
+    },
+    Example {
+        name: "Sierpinski Arrowhead",
+        axiom: "A",
+        variables_to_draw: "AB",
+        rules: &["A=>B-A-B", "B=>A+B+A"],
+        angle_degrees: 60,
+        iterations: 7,
+    },
+    Example {
+        name: "Dragon",
+        axiom: "FX",
+        variables_to_draw: "F",
+        rules: &["X=>X+YF+", "Y=>-FX-Y", "F=>F"],
+        angle_degrees: 90,
+        iterations: 4,
+    },
+    Example {
+        name: "Plant",
+        axiom: "X",
+        variables_to_draw: "F",
+        rules: &["X=>F-[[X]+X]+F[+FX]-X", "F=>FF"],
+        angle_degrees: 25,
+        iterations: 5,
+    },
+    Example {
+        name: "Moore",
+        axiom: "LFL+F+LFL",
+        variables_to_draw: "F",
+        rules: &["L=>-RF+LFL+FR-", "R=>+LF-RFR-FL+", "F=>F"],
+        angle_degrees: 90,
+        iterations: 5,
+    },
+    Example {
+        name: "Hilbert",
+        axiom: "A",
+        variables_to_draw: "F",
+        rules: &["A=>-BF+AFA+FB-", "B=>+AF-BFB-FA+", "F=>F"],
+        angle_degrees: 90,
+        iterations: 6,
+    },
+    Example {
+        name: "Sierpinski Carpet",
+        axiom: "F+F+F+F",
+        variables_to_draw: "F",
+        rules: &["F=>FF+F+F+F+FF"],
+        angle_degrees: 90,
+        iterations: 4,
+    },
+    Example {
+        name: "Snowflake",
+        axiom: "F++F++F",
+        variables_to_draw: "F",
+        rules: &["F=>F-F++F-F"],
+        angle_degrees: 60,
+        iterations: 4,
+    },
+    Example {
+        name: "Gosper",
+        axiom: "XF",
+        variables_to_draw: "F",
+        rules: &[
+            "X=>X+YF++YF-FX--FXFX-YF+",
+            "Y=>-FX+YFYF++YF+FX--FX-Y",
+            "F=>F",
+        ],
+        angle_degrees: 60,
+        iterations: 5,
+    },
+    Example {
+        name: "Kolam",
+        axiom: "-D--D",
+        variables_to_draw: "F",
+        rules: &[
+            "A=>F++FFFF--F--FFFF++F++FFFF--F",
+            "B=>F--FFFF++F++FFFF--F--FFFF++F",
+            "C=>BFA--BFA",
+            "D=>CFC--CFC",
+            "F=>F",
+        ],
+        angle_degrees: 45,
+        iterations: 7,
+    },
+];
@@ -1,29 +1,220 @@
 //! A crate for visualizing 2D [L-systems](https://en.wikipedia.org/wiki/L-system) with SVGs.
 
+use rand::{Rng, SeedableRng};
+use rand_chacha::ChaCha8Rng;
 use rust_decimal::Decimal;
 use rust_decimal::MathematicalOps;
+use std::borrow::Cow;
 use std::collections::HashMap;
 use std::collections::HashSet;
+use std::convert::Infallible;
 use std::io::Write;
+use std::str::FromStr;
 use svgtypes::LengthUnit;
 
+mod composite;
+mod examples;
+mod palette;
+pub mod parametric;
+mod plotter;
+mod prepared;
+pub mod tokenized;
+
+pub use composite::{render_many, Placement};
+pub use examples::{examples, Example};
+pub use palette::{load_gpl_palette, parse_gpl_palette, PaletteError};
+pub use plotter::PlotterOptions;
+pub use prepared::Prepared;
+
 /// Parameters for the L-system
-#[derive(Debug, Clone)]
+///
+/// The turtle interprets these characters in the final state:
+///
+/// | Character | Meaning |
+/// |-----------|---------|
+/// | `+` | Turn by [`Self::angle`] |
+/// | `-` | Turn by [`Self::angle`] in the other direction |
+/// | `\|` | Turn around by 180° |
+/// | `@` | Reset the heading to [`Self::initial_heading`] |
+/// | `[` | Save the current position and heading |
+/// | `]` | Restore the last saved position and heading |
+/// | `'` or `;` | Switch to the next color in [`SvgOptions::palette`] |
+/// | `!` | Decrement the line width, switching to the next width in [`SvgOptions::width_palette`] |
+/// | `.` | Draw a dot at the current position, with [`SvgOptions::dot_radius`] |
+///
+/// Variables in [`Self::variables_to_draw`] move the turtle forward while drawing, and variables in
+/// [`Self::variables_to_move`] move it forward without drawing. Characters in
+/// [`Self::turn_angles`] turn by their own angle.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct LSystem<A: AsRef<str>, R: AsRef<str>> {
     /// Initial string.
     pub axiom: A,
     /// Variables that should be treated as a stroke and drawn.
+    #[cfg_attr(feature = "serde", serde(serialize_with = "sorted::set"))]
     pub variables_to_draw: HashSet<char>,
+    /// Variables that move the turtle forward without drawing.
+    #[cfg_attr(feature = "serde", serde(default, serialize_with = "sorted::set"))]
+    pub variables_to_move: HashSet<char>,
+    /// Draw every character that is not an operator, a variable to move or one of
+    /// [`Self::turn_angles`], as if it were in [`Self::variables_to_draw`].
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub draw_all: bool,
     /// Turn angle in radians.
     pub angle: Decimal,
+    /// Additional turn operators and their angles in radians, with negative angles turning in the
+    /// same direction as `-`.
+    #[cfg_attr(feature = "serde", serde(default, serialize_with = "sorted::map"))]
+    pub turn_angles: HashMap<char, Decimal>,
     /// Number of times the rules will run.
+    ///
+    /// With 0, the axiom is drawn as is. The state can grow exponentially with each iteration,
+    /// so set [`Self::max_state_len`] to fail with [`LSystemError::StateTooLarge`] instead of
+    /// running out of memory.
     pub iterations: usize,
     /// Rules for replacing characters with a new string.
+    ///
+    /// Characters without a rule are replaced with themselves.
+    #[cfg_attr(feature = "serde", serde(serialize_with = "sorted::map"))]
     pub rules: HashMap<char, R>,
+    /// Rules that only apply between certain neighbors, taking precedence over [`Self::rules`].
+    ///
+    /// The first matching rule is used.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub context_rules: Vec<ContextRule<R>>,
+    /// Heading of the turtle before drawing, in radians.
+    ///
+    /// Defaults to `-π/2`, pointing up.
+    #[cfg_attr(feature = "serde", serde(default = "default_initial_heading"))]
+    pub initial_heading: Decimal,
+    /// Whether `+` turns clockwise as drawn, with y growing downward like in an SVG, rather than
+    /// counterclockwise like in most textbooks.
+    ///
+    /// Turning the other way mirrors the drawing about [`Self::initial_heading`], which applies to
+    /// [`Self::turn_angles`] as well.
+    #[cfg_attr(feature = "serde", serde(default = "default_clockwise"))]
+    pub clockwise: bool,
+    /// Position of the turtle before drawing, in the units of [`Self::step_lengths`].
+    ///
+    /// Drawings are normalized to fit the canvas, so this only moves them relative to each other
+    /// with [`SvgOptions::tight_view_box`] or when combining their strokes.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub origin: (Decimal, Decimal),
+    /// Distance moved by drawn variables.
+    ///
+    /// Variables without a step length move one unit.
+    #[cfg_attr(feature = "serde", serde(default, serialize_with = "sorted::map"))]
+    pub step_lengths: HashMap<char, Decimal>,
+    /// Maximum length of the state in bytes, to reject L-systems that grow too large to render.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub max_state_len: Option<usize>,
+    /// Maximum number of branches (`[`) the turtle can be inside of at once, to reject
+    /// L-systems that nest too deeply with [`RenderError::MaxDepthExceeded`].
+    ///
+    /// Only rendering checks it; methods that cannot fail, like [`Self::strokes`], ignore it.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub max_stack_depth: Option<usize>,
+    /// Largest random change to each `+` and `-` turn in radians, for a more natural look.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub angle_jitter: Decimal,
+    /// Seed for the random changes to turns from [`Self::angle_jitter`], so drawings can be
+    /// reproduced.
+    ///
+    /// Methods like [`Self::strokes_with_rng`] take a generator of their own instead.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub seed: u64,
+}
+
+impl<A, R> Default for LSystem<A, R>
+where
+    A: AsRef<str> + Default,
+    R: AsRef<str>,
+{
+    fn default() -> Self {
+        Self {
+            axiom: A::default(),
+            variables_to_draw: HashSet::new(),
+            variables_to_move: HashSet::new(),
+            draw_all: false,
+            angle: Decimal::ZERO,
+            turn_angles: HashMap::new(),
+            iterations: 0,
+            rules: HashMap::new(),
+            context_rules: vec![],
+            initial_heading: default_initial_heading(),
+            clockwise: default_clockwise(),
+            origin: (Decimal::ZERO, Decimal::ZERO),
+            step_lengths: HashMap::new(),
+            max_state_len: None,
+            max_stack_depth: None,
+            angle_jitter: Decimal::ZERO,
+            seed: 0,
+        }
+    }
+}
+
+/// Rule for replacing a character with a new string only when its neighbors match (i.e. `A<B>C=>D`).
+///
+/// Neighbors are found by skipping over branches (`[...]`) and other operators, so the left
+/// neighbor of a character in a branch is the one before the branch.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ContextRule<R: AsRef<str>> {
+    /// Required left neighbor, if any.
+    pub left: Option<char>,
+    /// Character to replace.
+    pub variable: char,
+    /// Required right neighbor, if any.
+    pub right: Option<char>,
+    /// Replacement string.
+    pub replacement: R,
+}
+
+/// Character before `state[i]`, skipping over branches and operators.
+fn left_neighbor(state: &[char], i: usize) -> Option<char> {
+    let mut depth = 0usize;
+    for &c in state[..i].iter().rev() {
+        match c {
+            ']' => depth += 1,
+            '[' => depth = depth.saturating_sub(1),
+            c if depth == 0 && !is_operator(c) => return Some(c),
+            _ => {}
+        }
+    }
+    None
+}
+
+/// Character after `state[i]`, skipping over branches and operators.
+fn right_neighbor(state: &[char], i: usize) -> Option<char> {
+    let mut depth = 0usize;
+    for &c in &state[i + 1..] {
+        match c {
+            '[' => depth += 1,
+            // End of the branch that `state[i]` is in
+            ']' if depth == 0 => return None,
+            ']' => depth -= 1,
+            c if depth == 0 && !is_operator(c) => return Some(c),
+            _ => {}
+        }
+    }
+    None
+}
+
+fn default_initial_heading() -> Decimal {
+    -Decimal::HALF_PI
+}
+
+fn default_clockwise() -> bool {
+    true
 }
 
-/// Options to control the SVG created using [cairo](https://www.cairographics.org/).
-#[derive(Debug, Clone)]
+/// Options to control the SVG written by [`LSystem::to_svg`], which is plain Rust with no need for
+/// cairo.
+///
+/// With the `cairo` feature, `LSystem::to_png` rasterizes with the same options.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(default))]
 pub struct SvgOptions {
     /// Width in [`Self::units`].
     pub width: Decimal,
@@ -31,15 +222,596 @@ pub struct SvgOptions {
     pub height: Decimal,
     /// Units used by the SVG
     ///
+    /// With [`LengthUnit::Percent`], the SVG fills that share of its container and the drawing is
+    /// scaled to it through the `viewBox`.
+    ///
     /// <https://www.w3.org/TR/SVG/coords.html#Units>
+    #[cfg_attr(feature = "serde", serde(with = "length_unit"))]
     pub units: LengthUnit,
+    /// Stroke colors for drawn variables, as any SVG color (i.e. `green` or `#00ff00`).
+    ///
+    /// Variables without a color are drawn in black.
+    #[cfg_attr(feature = "serde", serde(serialize_with = "sorted::map"))]
+    pub variable_colors: HashMap<char, String>,
+    /// Stroke colors selected with the `'` and `;` operators, taking precedence over
+    /// [`Self::variable_colors`] when non-empty.
+    ///
+    /// Drawing starts with the first color and wraps around after the last. Colors can be read
+    /// from a GIMP palette with [`load_gpl_palette`].
+    pub palette: Vec<String>,
+    /// Width of strokes, relative to the drawing which is scaled to span 0 to 1.
+    ///
+    /// Defaults to one of [`Self::units`] on the canvas.
+    pub stroke_width: Option<Decimal>,
+    /// Length of one step of the turtle in [`Self::units`], drawing at that size from the top left
+    /// of the canvas (inside the margin) instead of scaling the drawing to fill it.
+    ///
+    /// [`Self::stroke_width`] is then in [`Self::units`] as well. With
+    /// [`Self::tight_view_box`], the canvas is sized to fit the drawing.
+    pub unit_length: Option<Decimal>,
+    /// Fraction of the canvas left blank on each side, from 0 up to but not including 0.5.
+    ///
+    /// Other margins fail to render with [`RenderError::InvalidMargin`].
+    pub margin: Decimal,
+    /// Scale both axes of the drawing uniformly and center it, instead of stretching it to fill
+    /// the canvas.
+    pub preserve_aspect_ratio: bool,
+    /// Write the path in the turtle's own coordinates with a `viewBox` around its bounding box,
+    /// instead of normalizing it into a `0 0 width height` viewBox with a transform.
+    ///
+    /// [`Self::stroke_width`] is then in the turtle's coordinates as well.
+    pub tight_view_box: bool,
+    /// Fit the drawing to the canvas, which can be turned off to keep the turtle's own coordinates
+    /// like [`Self::tight_view_box`] does, but without stretching the `viewBox` over the canvas.
+    pub normalize: bool,
+    /// Negate y coordinates so they grow upward like in math, instead of downward like in an SVG,
+    /// for L-systems written with a heading of `π/2` pointing up.
+    pub flip_y: bool,
+    /// Size in the `viewBox` that the normalized drawing, which spans 0 to 1, is scaled to along
+    /// each axis, instead of the [`Self::width`] and [`Self::height`] of the canvas.
+    ///
+    /// This and [`Self::content_offset`] make up the transform of the drawing, so they are not
+    /// used with [`Self::tight_view_box`], [`Self::unit_length`] or without [`Self::normalize`].
+    pub content_scale: Option<(Decimal, Decimal)>,
+    /// Position in the `viewBox` that the normalized drawing is moved to after scaling it by
+    /// [`Self::content_scale`].
+    pub content_offset: (Decimal, Decimal),
+    /// Shape at the ends of strokes.
+    pub line_cap: LineCap,
+    /// Shape at the corners between strokes.
+    pub line_join: LineJoin,
+    /// Seconds over which each path draws itself, by animating `stroke-dashoffset`.
+    pub animation_duration: Option<Decimal>,
+    /// Draw strokes without a color from [`Self::variable_colors`] or [`Self::palette`] in black
+    /// or white to match the viewer's `prefers-color-scheme`, using CSS.
+    ///
+    /// They stay in the color of [`Self::theme`] where CSS is not supported.
+    pub adaptive_stroke_color: bool,
+    /// Color scheme that strokes without a color from [`Self::variable_colors`] or
+    /// [`Self::palette`] are drawn for, on a transparent background.
+    pub theme: Theme,
+    /// Draw each continuous run of strokes as its own `<polyline>`, instead of one `<path>` per
+    /// color.
+    pub polylines: bool,
+    /// Number of decimal places that coordinates are rounded to.
+    ///
+    /// Fewer decimal places make for a smaller SVG, at the cost of accuracy.
+    pub coordinate_precision: u32,
+    /// Merge consecutive strokes of the same color that continue in the same direction into one
+    /// line, so that `FFFF` is drawn with a single `L`.
+    pub collapse_collinear: bool,
+    /// Write path commands relative to the previous point (i.e. `l 0 1`), which are shorter than
+    /// absolute ones.
+    pub relative_commands: bool,
+    /// Fill color of the areas enclosed by each run of strokes, as any SVG color, closing each
+    /// run with `Z`.
+    pub fill: Option<String>,
+    /// Draw strokes, which can be turned off to only show [`Self::fill`].
+    pub stroke: bool,
+    /// How [`Self::fill`] decides what is inside curves that cross themselves.
+    pub fill_rule: FillRule,
+    /// Hint for how renderers should trade off speed, crisp edges and accuracy when drawing
+    /// strokes, such as [`ShapeRendering::CrispEdges`] for pixel-aligned lines at small sizes.
+    ///
+    /// Left to the renderer when `None`.
+    pub shape_rendering: Option<ShapeRendering>,
+    /// Opacity of strokes, from 0 to 1.
+    pub stroke_opacity: Decimal,
+    /// Factor that [`Self::stroke_opacity`] is multiplied by for each branch (`[`) that strokes
+    /// are inside of, so that deeper branches fade out.
+    ///
+    /// Strokes of each opacity are drawn as their own path.
+    pub depth_opacity_fade: Option<Decimal>,
+    /// Factor that [`Self::stroke_width`] is multiplied by for each branch (`[`) that strokes are
+    /// inside of, so that branches get thinner toward their tips.
+    ///
+    /// Strokes of each width are drawn as their own path.
+    pub width_taper: Decimal,
+    /// Factors that [`Self::stroke_width`] is multiplied by, starting with the first and moving to
+    /// the next with each `!`, like the decreasing line widths of *The Algorithmic Beauty of
+    /// Plants*.
+    ///
+    /// The last factor is kept once the turtle reaches it. The width is restored at the end of
+    /// each branch (`]`), and strokes of each width are drawn as their own path. Widths are not
+    /// changed when this is empty.
+    pub width_palette: Vec<Decimal>,
+    /// Wrap the strokes of each branch (`[...]`) in a `<g>`, nested like the branches are, so that
+    /// whole branches can be selected in editors like Inkscape.
+    pub branch_groups: bool,
+    /// Draw each outermost branch (`[...]`) in its own hue, along with the branches inside of it,
+    /// taking precedence over [`Self::palette`] and [`Self::variable_colors`].
+    ///
+    /// Hues are a golden angle apart, so that neighboring branches stand out from each other, and
+    /// only depend on the order of the branches. Strokes outside of any branch keep their color.
+    /// With [`Self::branch_groups`], each branch is also its own `<g>`.
+    pub branch_colorize: bool,
+    /// Title of the SVG for screen readers and asset managers, in a `<title>`.
+    pub title: Option<String>,
+    /// Description of the SVG for screen readers and asset managers, in a `<desc>`.
+    pub description: Option<String>,
+    /// Start with an `<?xml ...?>` declaration, which can be turned off for SVGs embedded in HTML.
+    pub xml_declaration: bool,
+    /// Radius of a dot drawn at each of [`LSystem::leaf_positions`], in the same units as
+    /// [`Self::stroke_width`].
+    pub leaf_dot_radius: Option<Decimal>,
+    /// SVG drawn at each of [`LSystem::leaf_positions`], with its origin at the leaf and in the
+    /// units of the `viewBox`.
+    pub leaf_marker: Option<String>,
+    /// Radius of the dots drawn with `.`, in the same units as [`Self::stroke_width`].
+    ///
+    /// Defaults to the width of strokes.
+    pub dot_radius: Option<Decimal>,
+}
+
+impl Default for SvgOptions {
+    /// A 100mm x 100mm canvas.
+    fn default() -> Self {
+        Self {
+            width: Decimal::ONE_HUNDRED,
+            height: Decimal::ONE_HUNDRED,
+            units: LengthUnit::Mm,
+            variable_colors: HashMap::new(),
+            palette: vec![],
+            stroke_width: None,
+            unit_length: None,
+            margin: Decimal::ZERO,
+            preserve_aspect_ratio: false,
+            tight_view_box: false,
+            normalize: true,
+            flip_y: false,
+            content_scale: None,
+            content_offset: (Decimal::ZERO, Decimal::ZERO),
+            line_cap: LineCap::default(),
+            line_join: LineJoin::default(),
+            animation_duration: None,
+            adaptive_stroke_color: false,
+            theme: Theme::default(),
+            polylines: false,
+            coordinate_precision: 7,
+            collapse_collinear: false,
+            relative_commands: false,
+            fill: None,
+            stroke: true,
+            fill_rule: FillRule::default(),
+            shape_rendering: None,
+            stroke_opacity: Decimal::ONE,
+            depth_opacity_fade: None,
+            width_taper: Decimal::ONE,
+            width_palette: vec![],
+            branch_groups: false,
+            branch_colorize: false,
+            title: None,
+            description: None,
+            xml_declaration: true,
+            leaf_dot_radius: None,
+            leaf_marker: None,
+            dot_radius: None,
+        }
+    }
+}
+
+/// Color scheme of an SVG, from [`SvgOptions::theme`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "lowercase"))]
+pub enum Theme {
+    /// Black strokes, for a light background.
+    #[default]
+    Light,
+    /// White strokes, for a dark background.
+    Dark,
+}
+
+impl Theme {
+    /// Color of strokes that are not given one.
+    fn stroke_color(self) -> &'static str {
+        match self {
+            Theme::Light => "rgb(0%, 0%, 0%)",
+            Theme::Dark => "rgb(100%, 100%, 100%)",
+        }
+    }
+}
+
+/// Value of the SVG `stroke-linecap` attribute.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "lowercase"))]
+pub enum LineCap {
+    #[default]
+    Butt,
+    Round,
+    Square,
+}
+
+impl LineCap {
+    fn as_str(self) -> &'static str {
+        match self {
+            LineCap::Butt => "butt",
+            LineCap::Round => "round",
+            LineCap::Square => "square",
+        }
+    }
+}
+
+/// Value of the SVG `stroke-linejoin` attribute.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "lowercase"))]
+pub enum LineJoin {
+    #[default]
+    Miter,
+    Round,
+    Bevel,
+}
+
+impl LineJoin {
+    fn as_str(self) -> &'static str {
+        match self {
+            LineJoin::Miter => "miter",
+            LineJoin::Round => "round",
+            LineJoin::Bevel => "bevel",
+        }
+    }
+}
+
+/// Value of the SVG `fill-rule` attribute.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "lowercase"))]
+pub enum FillRule {
+    #[default]
+    NonZero,
+    EvenOdd,
+}
+
+impl FillRule {
+    fn as_str(self) -> &'static str {
+        match self {
+            FillRule::NonZero => "nonzero",
+            FillRule::EvenOdd => "evenodd",
+        }
+    }
+}
+
+/// Value of the SVG `shape-rendering` attribute.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "camelCase"))]
+pub enum ShapeRendering {
+    OptimizeSpeed,
+    CrispEdges,
+    GeometricPrecision,
+}
+
+impl ShapeRendering {
+    fn as_str(self) -> &'static str {
+        match self {
+            ShapeRendering::OptimizeSpeed => "optimizeSpeed",
+            ShapeRendering::CrispEdges => "crispEdges",
+            ShapeRendering::GeometricPrecision => "geometricPrecision",
+        }
+    }
+}
+
+/// Suffix of `unit` in SVG lengths (i.e. `"mm"`).
+pub fn length_unit_suffix(unit: LengthUnit) -> &'static str {
+    match unit {
+        LengthUnit::None => "",
+        LengthUnit::Em => "em",
+        LengthUnit::Ex => "ex",
+        LengthUnit::Px => "px",
+        LengthUnit::In => "in",
+        LengthUnit::Cm => "cm",
+        LengthUnit::Mm => "mm",
+        LengthUnit::Pt => "pt",
+        LengthUnit::Pc => "pc",
+        LengthUnit::Percent => "%",
+    }
+}
+
+/// Serializes sets and maps in sorted order, so the same L-system is always written the same way.
+#[cfg(feature = "serde")]
+mod sorted {
+    use serde::{Serialize, Serializer};
+    use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+
+    pub fn set<T, S>(set: &HashSet<T>, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        T: Ord + Serialize,
+        S: Serializer,
+    {
+        set.iter().collect::<BTreeSet<_>>().serialize(serializer)
+    }
+
+    pub fn map<K, V, S>(map: &HashMap<K, V>, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        K: Ord + Serialize,
+        V: Serialize,
+        S: Serializer,
+    {
+        map.iter().collect::<BTreeMap<_, _>>().serialize(serializer)
+    }
+}
+
+/// (De)serializes [`LengthUnit`] as its suffix in SVG lengths (i.e. `"mm"`).
+#[cfg(feature = "serde")]
+mod length_unit {
+    use super::length_unit_suffix;
+    use serde::{de::Error, Deserialize, Deserializer, Serializer};
+    use svgtypes::LengthUnit;
+
+    pub fn serialize<S>(units: &LengthUnit, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.serialize_str(length_unit_suffix(*units))
+    }
+
+    pub fn deserialize<'de, D>(deserializer: D) -> Result<LengthUnit, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let suffix = String::deserialize(deserializer)?;
+        [
+            LengthUnit::None,
+            LengthUnit::Em,
+            LengthUnit::Ex,
+            LengthUnit::Px,
+            LengthUnit::In,
+            LengthUnit::Cm,
+            LengthUnit::Mm,
+            LengthUnit::Pt,
+            LengthUnit::Pc,
+            LengthUnit::Percent,
+        ]
+        .into_iter()
+        .find(|&unit| length_unit_suffix(unit) == suffix)
+        .ok_or_else(|| D::Error::custom(format!("unknown unit `{suffix}`")))
+    }
+}
+
+/// Whether `c` is interpreted by the turtle instead of being rewritten by rules.
+pub fn is_operator(c: char) -> bool {
+    matches!(
+        c,
+        '+' | '-' | '|' | '@' | '[' | ']' | '\'' | ';' | '!' | '.'
+    )
+}
+
+/// A straight movement of the turtle, in the units of [`LSystem::step_lengths`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Stroke {
+    /// Position before moving.
+    pub start: (Decimal, Decimal),
+    /// Position after moving.
+    pub end: (Decimal, Decimal),
+    /// Whether the turtle moved without drawing a line.
+    pub is_move: bool,
+    /// Variable that moved the turtle, if any.
+    pub variable: Option<char>,
+    /// Index into [`SvgOptions::palette`] selected with `'` or `;`.
+    pub color: usize,
+    /// Index into [`SvgOptions::width_palette`] selected with `!`.
+    pub width: usize,
+    /// Index of the outermost branch (`[...]`) the turtle is inside of, counting from 0, for
+    /// [`SvgOptions::branch_colorize`].
+    pub branch: Option<usize>,
+    /// Number of branches (`[`) the turtle is inside of.
+    pub depth: usize,
+}
+
+/// Statistics about the final state of an L-system, from [`LSystem::stats`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct LSystemStats {
+    /// Length of the final state in characters.
+    pub state_len: usize,
+    /// Number of lines drawn.
+    pub draw_count: usize,
+    /// Number of matching `[` and `]` pairs.
+    pub branch_count: usize,
+    /// Deepest nesting of `[`.
+    pub max_stack_depth: usize,
+    /// Width and height of the drawing's bounding box, in the units of [`LSystem::step_lengths`].
+    pub extents: (Decimal, Decimal),
+}
+
+/// Parse rules for replacing characters with a new string, one per line (i.e. `F=>F+F`).
+///
+/// A `#` at the start of a line or after whitespace starts a comment, unless it is followed by
+/// `=>`, so `#` can still be used as a letter (i.e. `#=>F#F`). Whitespace is ignored, so rules
+/// can be indented and spaced out (i.e. `  F => F + F  # koch`). Blank lines are skipped.
+pub fn parse_rules(rules: &str) -> Result<HashMap<char, String>, ParseError> {
+    rules
+        .lines()
+        .map(strip_comment)
+        .enumerate()
+        .filter(|(_, rule)| !rule.trim().is_empty())
+        .map(|(i, rule)| {
+            let line = i + 1;
+            let (variable, replacement) = rule
+                .split_once("=>")
+                .ok_or(ParseError::MissingArrow { line })?;
+            let mut variable_chars = variable.trim().chars();
+            let (Some(variable), None) = (variable_chars.next(), variable_chars.next()) else {
+                return Err(ParseError::InvalidVariable { line });
+            };
+            let replacement = replacement
+                .chars()
+                .filter(|c| !c.is_whitespace())
+                .collect::<String>();
+            if replacement.is_empty() {
+                return Err(ParseError::EmptyReplacement { line });
+            }
+            Ok((variable, replacement))
+        })
+        .collect()
+}
+
+/// Part of a line of rules before its comment, which starts with a `#` at the start of the line or
+/// after whitespace that is not the letter of a rule.
+pub(crate) fn strip_comment(line: &str) -> &str {
+    // Whether the previous char was whitespace, or there was none
+    let mut after_whitespace = true;
+    for (i, c) in line.char_indices() {
+        if c == '#' && after_whitespace && !line[i + 1..].starts_with("=>") {
+            return &line[..i];
+        }
+        after_whitespace = c.is_whitespace();
+    }
+    line
+}
+
+impl FromStr for LSystem<String, String> {
+    type Err = CompactGrammarError;
+
+    /// Parse an L-system from one line of the form `axiom;draw;angle;iterations;rules`, like
+    /// `F;F;90;4;F=>F+F-F-F+F`.
+    ///
+    /// The angle is in degrees, and rules are separated by commas. Whitespace around each field is
+    /// ignored.
+    ///
+    /// Since fields are separated by `;`, the axiom and rules cannot contain the `;` operator.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let fields = s.split(';').map(str::trim).collect::<Vec<_>>();
+        let [axiom, variables_to_draw, angle, iterations, rules] = fields[..] else {
+            return Err(CompactGrammarError::FieldCount(fields.len()));
+        };
+        if axiom.is_empty() {
+            return Err(CompactGrammarError::EmptyAxiom);
+        }
+        let angle = angle
+            .parse::<Decimal>()
+            .map_err(|_| CompactGrammarError::InvalidAngle(angle.to_string()))?;
+        let iterations = iterations
+            .parse()
+            .map_err(|_| CompactGrammarError::InvalidIterations(iterations.to_string()))?;
+        let rules = parse_rules(&rules.split(',').collect::<Vec<_>>().join("\n"))?;
+
+        Ok(LSystem {
+            axiom: axiom.to_string(),
+            variables_to_draw: variables_to_draw
+                .chars()
+                .filter(|c| !c.is_whitespace())
+                .collect(),
+            iterations,
+            rules,
+            ..Default::default()
+        }
+        .with_angle_degrees(angle))
+    }
+}
+
+/// Error type for [`parse_rules`] and [`tokenized::parse_rules`].
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum ParseError {
+    #[error("line {line}: each rule must contain =>")]
+    MissingArrow { line: usize },
+    #[error("line {line}: => must be preceded by a single char")]
+    InvalidVariable { line: usize },
+    #[error("line {line}: => must be followed by a replacement string")]
+    EmptyReplacement { line: usize },
+    #[error("line {line}: => must be preceded by a single module")]
+    InvalidModule { line: usize },
+    #[error("line {line}: operators cannot be rewritten")]
+    OperatorModule { line: usize },
+}
+
+/// Error type for parsing an [`LSystem`] from a single line with [`str::parse`].
+///
+/// Errors in the rules count each comma-separated rule as a line, starting from 1.
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum CompactGrammarError {
+    #[error("expected 5 fields separated by `;` (axiom;draw;angle;iterations;rules), found {0}")]
+    FieldCount(usize),
+    #[error("the axiom is empty")]
+    EmptyAxiom,
+    #[error("`{0}` is not a valid angle in degrees")]
+    InvalidAngle(String),
+    #[error("`{0}` is not a valid number of iterations")]
+    InvalidIterations(String),
+    #[error("invalid rules: {0}")]
+    InvalidRules(#[from] ParseError),
+}
+
+/// Error type for [`LSystem::try_calculate_final_state`], [`LSystem::validate`] and
+/// [`LSystemBuilder::build`].
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum LSystemError {
+    #[error("there is no replacement rule for `{0}`")]
+    MissingRule(char),
+    #[error("the state would be {produced} bytes long, exceeding the limit of {limit}")]
+    StateTooLarge { produced: usize, limit: usize },
+    #[error("{0} `]` without a matching `[`")]
+    UnmatchedClose(usize),
+    #[error("{0} `[` without a matching `]`")]
+    UnclosedBranch(usize),
+    #[error("nothing is drawn")]
+    NothingToDraw,
+    #[error("the angle is zero, so `+` and `-` do not turn")]
+    ZeroAngle,
+    #[error("the angle of {0} radians is more than a full turn; was it meant in degrees?")]
+    AngleOutOfRange(Decimal),
+    #[error("the turtle moves too far for its position to be represented")]
+    PositionOverflow,
 }
 
-/// Error type for [`LSystem::to_svg`].
+/// Error type for [`LSystem::to_svg`] and `LSystem::to_png`.
 #[derive(Debug, thiserror::Error)]
 pub enum RenderError {
     #[error("I/O error while writing SVG: {0}")]
     Io(#[from] std::io::Error),
+    #[error(transparent)]
+    LSystem(#[from] LSystemError),
+    #[error(transparent)]
+    Parametric(#[from] parametric::ParametricError),
+    #[error("branches are nested more than {0} deep")]
+    MaxDepthExceeded(usize),
+    #[error("rendering was cancelled")]
+    Cancelled,
+    #[error("{0} is not a margin from 0 up to but not including 0.5")]
+    InvalidMargin(Decimal),
+    #[cfg(feature = "cairo")]
+    #[error("cairo error while drawing PNG: {0}")]
+    Cairo(#[from] cairo::Error),
+    #[cfg(feature = "cairo")]
+    #[error("{0} is not a valid image size in pixels")]
+    InvalidImageSize(Decimal),
+}
+
+/// How far [`LSystem::to_svg_with_progress`] has gotten.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Progress {
+    /// The rules are being applied for the `iteration`th time, counting from 1.
+    Expanding { iteration: usize, iterations: usize },
+    /// The turtle has walked over `walked` of the `len` characters in the final state, drawing
+    /// `strokes` strokes so far.
+    Drawing {
+        walked: usize,
+        len: usize,
+        strokes: usize,
+    },
 }
 
 impl<A, R> LSystem<A, R>
@@ -48,173 +820,2049 @@ where
     R: AsRef<str>,
 {
     /// Runs the L-system, returning its final state.
+    ///
+    /// This is the axiom after [`Self::step`] is applied [`Self::iterations`] times.
+    /// Letters without a rule are replaced with themselves.
+    ///
+    /// With the `parallel` feature, large states are rewritten in parallel unless there are
+    /// [`Self::context_rules`].
+    ///
+    /// [`Self::max_state_len`] is not checked, as it is by [`Self::try_calculate_final_state`].
     pub fn calculate_final_state(&self) -> String {
         let mut state = self.axiom.as_ref().to_string();
         for _ in 0..self.iterations {
-            state = state
-                .chars()
-                .flat_map(|c| {
-                    match c {
-                        '+' => "+",
-                        '-' => "-",
-                        '|' => "|",
-                        '[' => "[",
-                        ']' => "]",
-                        letter => self
-                            .rules
-                            .get(&letter)
-                            .expect("rule exists for every letter")
-                            .as_ref(),
-                    }
-                    .chars()
-                })
-                .collect();
+            state = self.rewrite(&state);
         }
-
         state
     }
 
-    /// Run the L-system and convert it into an SVG.
-    pub fn to_svg<W>(
-        &self,
-        SvgOptions {
-            width,
-            height,
-            units,
-        }: &SvgOptions,
-        mut writer: W,
-    ) -> Result<(), RenderError>
-    where
-        W: Write,
-    {
-        let final_state = self.calculate_final_state();
+    /// Apply the rules to any `state` exactly once, to run the L-system one iteration at a time.
+    ///
+    /// Letters without a rule are replaced with themselves, and [`Self::max_state_len`] is not
+    /// checked.
+    pub fn step(&self, state: &str) -> String {
+        self.rewrite(state)
+    }
+
+    /// Runs the L-system, returning its final state.
+    ///
+    /// Unlike [`Self::calculate_final_state`], this fails with
+    /// [`LSystemError::MissingRule`] for any letter that does not have a rule and is not
+    /// interpreted by the turtle, like [`Self::validate`],
+    /// and [`LSystemError::StateTooLarge`] if the state grows longer than [`Self::max_state_len`].
+    pub fn try_calculate_final_state(&self) -> Result<String, LSystemError> {
+        self.expand(true)
+    }
 
-        let mut current_position = (Decimal::ZERO, Decimal::ZERO);
-        let mut current_angle = -Decimal::HALF_PI;
-        let mut strokes: Vec<((Decimal, Decimal), bool)> = vec![];
-        let mut stack: Vec<((Decimal, Decimal), Decimal)> = vec![];
-        for c in final_state.chars() {
+    /// Set [`Self::angle`] from an angle in degrees.
+    pub fn with_angle_degrees(mut self, degrees: impl Into<Decimal>) -> Self {
+        self.angle = degrees_to_radians(degrees.into());
+        self
+    }
+
+    /// Write the L-system in the grammar file format of the CLI, which starts with the axiom:
+    ///
+    /// ```text
+    /// F
+    /// draw: F
+    /// angle: 90
+    /// iterations: 4
+    /// F=>F+F-F-F+F
+    /// ```
+    ///
+    /// Variables and rules are sorted so the output is stable, and the angle is in degrees,
+    /// rounded to 10 decimal places. Other fields, like [`Self::context_rules`], have no place in
+    /// the format and are left out.
+    pub fn to_grammar_string(&self) -> String {
+        let mut variables_to_draw = self.variables_to_draw.iter().collect::<Vec<_>>();
+        variables_to_draw.sort();
+        let mut rules = self.rules.iter().collect::<Vec<_>>();
+        rules.sort_by_key(|(variable, _)| **variable);
+
+        let mut grammar = format!(
+            "{}\ndraw: {}\nangle: {}\niterations: {}\n",
+            self.axiom.as_ref(),
+            variables_to_draw.into_iter().collect::<String>(),
+            radians_to_degrees(self.angle).round_dp(10).normalize(),
+            self.iterations
+        );
+        for (variable, replacement) in rules {
+            grammar.push_str(&format!("{variable}=>{}\n", replacement.as_ref()));
+        }
+        grammar
+    }
+
+    /// Check the final state for problems before rendering it, collecting all of them.
+    ///
+    /// The final state must have balanced brackets and draw at least one line. Letters that have
+    /// no rule and are not interpreted by the turtle are reported as
+    /// [`LSystemError::MissingRule`], since they have no effect.
+    ///
+    /// [`Self::angle`] is also checked, since it is easy to get wrong: it must not be zero if the
+    /// final state turns with `+` or `-`, and should be at most a full turn, since larger angles
+    /// are usually degrees given as radians (see [`Self::with_angle_degrees`]).
+    pub fn validate(&self) -> Result<(), Vec<LSystemError>> {
+        let symbols = self.checked_symbols().map_err(|err| vec![err])?;
+
+        let mut errors = vec![];
+        let mut depth = 0usize;
+        let mut unmatched_close = 0;
+        let mut draws = false;
+        let mut turns = false;
+        for c in symbols {
             match c {
-                '+' | '-' | '|' => {
-                    current_angle = match c {
-                        '+' => current_angle + self.angle,
-                        '-' => current_angle - self.angle,
-                        '|' => -current_angle,
-                        _ => unreachable!(),
-                    };
+                '+' | '-' => turns = true,
+                '[' => depth += 1,
+                ']' if depth == 0 => unmatched_close += 1,
+                ']' => depth -= 1,
+                c if self.is_drawn(c) => draws = true,
+                c if self.is_defined(c) => {}
+                c => {
+                    let err = LSystemError::MissingRule(c);
+                    if !errors.contains(&err) {
+                        errors.push(err);
+                    }
                 }
-                '[' => {
-                    stack.push((current_position, current_angle));
+            }
+        }
+        if unmatched_close > 0 {
+            errors.push(LSystemError::UnmatchedClose(unmatched_close));
+        }
+        if depth > 0 {
+            errors.push(LSystemError::UnclosedBranch(depth));
+        }
+        if !draws {
+            errors.push(LSystemError::NothingToDraw);
+        }
+        if turns && self.angle.is_zero() {
+            errors.push(LSystemError::ZeroAngle);
+        }
+        if self.angle.abs() > Decimal::TWO_PI {
+            errors.push(LSystemError::AngleOutOfRange(self.angle));
+        }
+
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
+        }
+    }
+
+    /// Final state, failing once it grows longer than [`Self::max_state_len`] and, if `strict`,
+    /// at the first letter that is not [defined](Self::is_defined).
+    fn expand(&self, strict: bool) -> Result<String, LSystemError> {
+        self.check_state_len()?;
+        // Every state is checked, including the final one
+        let check_rules = |state: &str| {
+            if strict {
+                if let Some(missing) = state.chars().find(|&c| !self.is_defined(c)) {
+                    return Err(LSystemError::MissingRule(missing));
                 }
-                ']' => {
-                    let state = stack.pop().expect("equal number of [ and ]");
-                    current_position = state.0;
-                    current_angle = state.1;
-                    strokes.push((current_position, true));
+            }
+            Ok(())
+        };
+        let mut state = self.axiom.as_ref().to_string();
+        self.check_len(state.len())?;
+        check_rules(&state)?;
+        for _ in 0..self.iterations {
+            state = self.rewrite(&state);
+            self.check_len(state.len())?;
+            check_rules(&state)?;
+        }
+
+        Ok(state)
+    }
+
+    /// Apply the rules to `state` once.
+    fn rewrite(&self, state: &str) -> String {
+        match self.rewrite_with(state, || Ok::<_, Infallible>(())) {
+            Ok(next) => next,
+            Err(never) => match never {},
+        }
+    }
+
+    /// Apply the rules to `state` once, calling `poll` every [`PROGRESS_INTERVAL`] characters and
+    /// stopping at the first error it returns.
+    fn rewrite_with<E>(
+        &self,
+        state: &str,
+        mut poll: impl FnMut() -> Result<(), E>,
+    ) -> Result<String, E> {
+        // Replacements depend on neighbors, so the state cannot be split
+        #[cfg(feature = "parallel")]
+        if self.context_rules.is_empty() && state.len() >= PARALLEL_CHUNK_LEN {
+            return self.par_rewrite(state, poll);
+        }
+
+        let mut next = String::with_capacity(state.len());
+        if self.context_rules.is_empty() {
+            for (i, c) in state.chars().enumerate() {
+                if i % PROGRESS_INTERVAL == 0 {
+                    poll()?;
                 }
-                other if self.variables_to_draw.contains(&other) => {
-                    let cos = current_angle.cos();
-                    let sin = current_angle.sin();
-                    current_position = (current_position.0 + cos, current_position.1 + sin);
-                    strokes.push((current_position, false));
+                match self.replacement(c) {
+                    Some(replacement) => next.push_str(replacement),
+                    None => next.push(c),
                 }
-                _ => {}
             }
+            return Ok(next);
         }
 
-        let max = (
-            strokes
-                .iter()
-                .max_by_key(|((x, _y), _move)| x)
-                .cloned()
-                .expect("at least one stroke")
-                .0
-                 .0,
-            strokes
-                .iter()
-                .max_by_key(|((_x, y), _move)| y)
-                .cloned()
-                .expect("at least one stroke")
-                .0
-                 .1,
-        );
-        let min = (
-            strokes
-                .iter()
-                .min_by_key(|((x, _y), _move)| x)
-                .cloned()
-                .expect("at least one stroke")
-                .0
-                 .0,
-            strokes
+        let chars = state.chars().collect::<Vec<_>>();
+        for (i, &c) in chars.iter().enumerate() {
+            if i % PROGRESS_INTERVAL == 0 {
+                poll()?;
+            }
+            let replacement = self
+                .context_rules
                 .iter()
-                .min_by_key(|((_x, y), _move)| y)
-                .cloned()
-                .expect("at least one stroke")
-                .0
-                 .1,
-        );
+                .find(|rule| {
+                    rule.variable == c
+                        && (rule.left.is_none() || left_neighbor(&chars, i) == rule.left)
+                        && (rule.right.is_none() || right_neighbor(&chars, i) == rule.right)
+                })
+                .map(|rule| rule.replacement.as_ref())
+                .or_else(|| self.replacement(c));
+            match replacement {
+                Some(replacement) => next.push_str(replacement),
+                None => next.push(c),
+            }
+        }
+        Ok(next)
+    }
 
-        let units = match units {
-            LengthUnit::None => "",
-            LengthUnit::Em => "em",
-            LengthUnit::Ex => "ex",
-            LengthUnit::Px => "px",
-            LengthUnit::In => "in",
-            LengthUnit::Cm => "cm",
-            LengthUnit::Mm => "mm",
-            LengthUnit::Pt => "pt",
-            LengthUnit::Pc => "pc",
-            LengthUnit::Percent => "%",
-        };
-        writeln!(writer, r#"<?xml version="1.0" encoding="UTF-8"?>"#)?;
+    /// Apply context-free rules to `state` once, rewriting chunks of it in parallel.
+    ///
+    /// Chunks are rewritten in batches of one per thread, calling `poll` before each batch.
+    #[cfg(feature = "parallel")]
+    fn par_rewrite<E>(
+        &self,
+        state: &str,
+        mut poll: impl FnMut() -> Result<(), E>,
+    ) -> Result<String, E> {
+        use rayon::prelude::*;
 
-        writeln!(
-            writer,
-            r#"<svg xmlns="http://www.w3.org/2000/svg" xmlns:xlink="http://www.w3.org/1999/xlink" width="{width}{units}" height="{height}{units}" viewBox="0 0 {width} {height}">"#
-        )?;
+        // Only borrow the replacements, so `A` and `R` need not be `Sync`
+        let rules = self
+            .rules
+            .iter()
+            .map(|(&c, replacement)| (c, replacement.as_ref()))
+            .collect::<HashMap<_, _>>();
+        let mut chunks = vec![];
+        let mut rest = state;
+        while !rest.is_empty() {
+            let mut end = PARALLEL_CHUNK_LEN.min(rest.len());
+            while !rest.is_char_boundary(end) {
+                end += 1;
+            }
+            let (chunk, tail) = rest.split_at(end);
+            chunks.push(chunk);
+            rest = tail;
+        }
 
-        // 1 unit
-        let stroke_width = Decimal::ONE / width.min(height);
-        write!(
-            writer,
-            r#"<path fill="none" stroke-width="{stroke_width}" stroke-linecap="butt" stroke-linejoin="miter" stroke="rgb(0%, 0%, 0%)" stroke-opacity="1" stroke-miterlimit="10" d=""#
-        )?;
+        let mut next = String::with_capacity(state.len());
+        for batch in chunks.chunks(rayon::current_num_threads()) {
+            poll()?;
+            let rewritten = batch
+                .par_iter()
+                .map(|chunk| {
+                    let mut next = String::with_capacity(chunk.len());
+                    for c in chunk.chars() {
+                        match rules.get(&c) {
+                            Some(replacement) if !is_operator(c) => next.push_str(replacement),
+                            _ => next.push(c),
+                        }
+                    }
+                    next
+                })
+                .collect::<Vec<_>>();
+            next.extend(rewritten);
+        }
+        Ok(next)
+    }
 
-        let range = ((max.0 - min.0), (max.1 - min.1));
-        strokes.iter_mut().for_each(|segment| {
-            *segment = (
-                (
-                    (segment.0 .0 - min.0) / range.0,
-                    (segment.0 .1 - min.1) / range.1,
-                ),
-                segment.1,
-            );
-        });
+    /// Runs the L-system, lazily producing the characters of its final state.
+    ///
+    /// Unlike [`Self::calculate_final_state`], the final state is never held in memory.
+    /// Letters without a rule are replaced with themselves.
+    ///
+    /// Context-sensitive L-systems need neighbors to be known, so the final state is calculated
+    /// up front when there are [`Self::context_rules`].
+    pub fn symbols(&self) -> impl Iterator<Item = char> + '_ {
+        Symbols {
+            l_system: self,
+            stack: vec![self.axiom.as_ref().chars()],
+            expanded: (!self.context_rules.is_empty()).then(|| {
+                self.calculate_final_state()
+                    .chars()
+                    .collect::<Vec<_>>()
+                    .into_iter()
+            }),
+        }
+    }
 
-        strokes.iter_mut().for_each(|((x, y), _)| {
-            *x = x.round_dp(7);
-            *y = y.round_dp(7);
-        });
+    /// Like [`Self::symbols`], but failing if the state grows longer than
+    /// [`Self::max_state_len`].
+    ///
+    /// When there are [`Self::context_rules`], the final state is only expanded once, checking
+    /// its length as it grows.
+    fn checked_symbols(&self) -> Result<Symbols<'_, A, R>, LSystemError> {
+        let expanded = if self.context_rules.is_empty() {
+            self.check_state_len()?;
+            None
+        } else {
+            Some(self.expand(false)?.chars().collect::<Vec<_>>().into_iter())
+        };
+        Ok(Symbols {
+            l_system: self,
+            stack: vec![self.axiom.as_ref().chars()],
+            expanded,
+        })
+    }
 
-        if let Some(((first_segment_x, first_segment_y), _)) = strokes.first() {
-            write!(writer, "M {first_segment_x} {first_segment_y}",)?;
+    /// Replacement for a letter, if it has a rule.
+    fn replacement(&self, c: char) -> Option<&str> {
+        if is_operator(c) {
+            None
+        } else {
+            self.rules.get(&c).map(AsRef::as_ref)
         }
-        for ((segment_x, segment_y), is_move) in &strokes[1..] {
-            write!(
-                writer,
-                " {} {segment_x} {segment_y}",
-                if *is_move { 'M' } else { 'L' },
-            )?;
+    }
+
+    /// Check that the state stays within [`Self::max_state_len`] without expanding it.
+    ///
+    /// Replacements of [`Self::context_rules`] depend on neighbors, so those states are not
+    /// checked here, but as they are rewritten.
+    fn check_state_len(&self) -> Result<(), LSystemError> {
+        if self.max_state_len.is_none() || !self.context_rules.is_empty() {
+            return Ok(());
         }
 
-        writeln!(
-            writer,
-            "\" transform=\"matrix({width}, 0, 0, {height}, 0, 0)\"/>",
-        )?;
+        let check = |counts: &HashMap<char, usize>| {
+            self.check_len(counts.iter().fold(0usize, |len, (c, count)| {
+                len.saturating_add(count.saturating_mul(c.len_utf8()))
+            }))
+        };
 
-        writeln!(writer, "</svg>")?;
+        // Occurrences of each character in the state
+        let mut counts = HashMap::new();
+        for c in self.axiom.as_ref().chars() {
+            *counts.entry(c).or_insert(0usize) += 1;
+        }
+        check(&counts)?;
+        for _ in 0..self.iterations {
+            let next = self.next_counts(&counts);
+            // Counts only depend on the previous ones, so the length will not change again
+            if next == counts {
+                break;
+            }
+            counts = next;
+            check(&counts)?;
+        }
 
         Ok(())
     }
+
+    /// Occurrences of each character in the state after one iteration, from those in the state
+    /// before it.
+    fn next_counts(&self, counts: &HashMap<char, usize>) -> HashMap<char, usize> {
+        let mut next = HashMap::new();
+        for (&c, &count) in counts {
+            match self.replacement(c) {
+                Some(replacement) => {
+                    for c in replacement.chars() {
+                        let entry = next.entry(c).or_insert(0usize);
+                        *entry = entry.saturating_add(count);
+                    }
+                }
+                None => {
+                    let entry = next.entry(c).or_insert(0usize);
+                    *entry = entry.saturating_add(count);
+                }
+            }
+        }
+        next
+    }
+
+    fn check_len(&self, produced: usize) -> Result<(), LSystemError> {
+        match self.max_state_len {
+            Some(limit) if produced > limit => Err(LSystemError::StateTooLarge { produced, limit }),
+            _ => Ok(()),
+        }
+    }
+
+    /// Run the L-system and walk the turtle over its final state, returning each movement.
+    pub fn strokes(&self) -> Vec<Stroke> {
+        self.trace(self.symbols(), &mut ChaCha8Rng::seed_from_u64(self.seed))
+            .strokes
+    }
+
+    /// Run the L-system and walk the turtle over its final state, like [`Self::strokes`], drawing
+    /// random changes to turns from `rng` instead of a generator seeded with [`Self::seed`].
+    pub fn strokes_with_rng<G>(&self, rng: &mut G) -> Vec<Stroke>
+    where
+        G: Rng,
+    {
+        self.trace(self.symbols(), rng).strokes
+    }
+
+    /// Run the L-system and walk the turtle over its final state, collecting statistics instead
+    /// of rendering it.
+    pub fn stats(&self) -> LSystemStats {
+        let mut state_len = 0;
+        let mut branch_count = 0;
+        let mut depth = 0usize;
+        let mut max_stack_depth = 0;
+        let strokes = self
+            .trace(
+                self.symbols().inspect(|&c| {
+                    state_len += 1;
+                    match c {
+                        '[' => {
+                            depth += 1;
+                            max_stack_depth = max_stack_depth.max(depth);
+                        }
+                        ']' if depth > 0 => {
+                            depth -= 1;
+                            branch_count += 1;
+                        }
+                        _ => {}
+                    }
+                }),
+                &mut ChaCha8Rng::seed_from_u64(self.seed),
+            )
+            .strokes;
+
+        LSystemStats {
+            state_len,
+            draw_count: strokes.iter().filter(|stroke| !stroke.is_move).count(),
+            branch_count,
+            max_stack_depth,
+            extents: bounding_box(&strokes)
+                .map(|(min_x, min_y, max_x, max_y)| (max_x - min_x, max_y - min_y))
+                .unwrap_or_default(),
+        }
+    }
+
+    /// Number of lines that will be drawn, like [`LSystemStats::draw_count`], without walking the
+    /// turtle.
+    ///
+    /// Only the occurrences of each character are tracked through the iterations, so the final
+    /// state is never held in memory unless there are [`Self::context_rules`].
+    pub fn draw_count(&self) -> usize {
+        let draws =
+            |c: &char| !is_operator(*c) && !self.turn_angles.contains_key(c) && self.is_drawn(*c);
+        if !self.context_rules.is_empty() {
+            return self.symbols().filter(draws).count();
+        }
+
+        let mut counts = HashMap::new();
+        for c in self.axiom.as_ref().chars() {
+            *counts.entry(c).or_insert(0usize) += 1;
+        }
+        for _ in 0..self.iterations {
+            let next = self.next_counts(&counts);
+            if next == counts {
+                break;
+            }
+            counts = next;
+        }
+        counts
+            .into_iter()
+            .filter(|(c, _)| draws(c))
+            .fold(0usize, |total, (_, count)| total.saturating_add(count))
+    }
+
+    /// Bounds of the points the turtle moves to, as `(min_x, min_y, max_x, max_y)` in the units of
+    /// [`Self::step_lengths`].
+    ///
+    /// This is the box that [`Self::to_svg`] scales to fit the canvas, or `None` if the turtle
+    /// never moves.
+    pub fn bounding_box(&self) -> Option<(Decimal, Decimal, Decimal, Decimal)> {
+        bounding_box(&self.strokes())
+    }
+
+    /// Width and height of [`Self::bounding_box`], in the units of [`Self::step_lengths`].
+    ///
+    /// Their ratio is the aspect ratio of the drawing, for picking an [`SvgOptions::width`] and
+    /// [`SvgOptions::height`] that do not leave empty space around it. Both are zero if the
+    /// turtle never moves.
+    pub fn dimensions(&self) -> (Decimal, Decimal) {
+        self.bounding_box().map_or(
+            (Decimal::ZERO, Decimal::ZERO),
+            |(min_x, min_y, max_x, max_y)| (max_x - min_x, max_y - min_y),
+        )
+    }
+
+    /// Total length of the lines drawn, in the units of [`Self::step_lengths`].
+    pub fn path_length(&self) -> Decimal {
+        self.strokes()
+            .iter()
+            .filter(|stroke| !stroke.is_move)
+            .map(|stroke| distance(stroke.start, stroke.end))
+            .sum()
+    }
+
+    /// Points every `spacing` units of length along the lines drawn, as `(x, y, heading)` with the
+    /// heading of the line in radians, to place text or objects along the curve.
+    ///
+    /// Samples start at the beginning of the first line, and length is only measured along lines,
+    /// so moves without drawing are skipped over.
+    ///
+    /// # Panics
+    ///
+    /// If `spacing` is not positive.
+    pub fn sample_along(&self, spacing: Decimal) -> Vec<(Decimal, Decimal, Decimal)> {
+        use rust_decimal::prelude::{FromPrimitive, ToPrimitive};
+
+        assert!(spacing > Decimal::ZERO, "spacing must be positive");
+        let mut samples = vec![];
+        // Length along the lines of the next sample, and of the start of the current line
+        let mut next = Decimal::ZERO;
+        let mut travelled = Decimal::ZERO;
+        for stroke in self.strokes().iter().filter(|stroke| !stroke.is_move) {
+            let length = distance(stroke.start, stroke.end);
+            let (dx, dy) = (stroke.end.0 - stroke.start.0, stroke.end.1 - stroke.start.1);
+            let heading = dy
+                .to_f64()
+                .zip(dx.to_f64())
+                .and_then(|(dy, dx)| Decimal::from_f64(dy.atan2(dx)))
+                .unwrap_or_default();
+            // Compared with some leeway, so samples at the ends of lines are not lost to rounding
+            while (next - travelled - length).round_dp(20) <= Decimal::ZERO {
+                let t = if length.is_zero() {
+                    Decimal::ZERO
+                } else {
+                    ((next - travelled) / length).min(Decimal::ONE)
+                };
+                samples.push((stroke.start.0 + dx * t, stroke.start.1 + dy * t, heading));
+                next += spacing;
+            }
+            travelled += length;
+        }
+        samples
+    }
+
+    /// Heading of the turtle in radians after walking over the final state, once any branches it
+    /// ends in are restored, to continue drawing from where the L-system leaves off.
+    pub fn heading_at_end(&self) -> Decimal {
+        self.trace(self.symbols(), &mut ChaCha8Rng::seed_from_u64(self.seed))
+            .heading
+    }
+
+    /// Positions of the turtle at the tips of branches, where a `]` ends a branch right after
+    /// drawing a line, to place glyphs like leaves or flowers.
+    pub fn leaf_positions(&self) -> Vec<(Decimal, Decimal)> {
+        self.trace(self.symbols(), &mut ChaCha8Rng::seed_from_u64(self.seed))
+            .leaves
+    }
+
+    /// Walk the turtle over `final_state`.
+    fn walk(
+        &self,
+        final_state: impl IntoIterator<Item = char>,
+    ) -> Result<Vec<Stroke>, RenderError> {
+        Ok(self.walk_turtle(final_state)?.strokes)
+    }
+
+    /// Turtle after walking over `final_state`, drawing random changes to turns from a generator
+    /// seeded with [`Self::seed`].
+    fn walk_turtle(
+        &self,
+        final_state: impl IntoIterator<Item = char>,
+    ) -> Result<Turtle, RenderError> {
+        self.turtle(final_state, &mut ChaCha8Rng::seed_from_u64(self.seed))
+    }
+
+    /// Turtle after walking over `final_state`, drawing random changes to turns from `rng`.
+    ///
+    /// Fails once the turtle would be inside more than [`Self::max_stack_depth`] branches, before
+    /// saving another position, or with [`LSystemError::PositionOverflow`] once it moves too far.
+    fn turtle<G>(
+        &self,
+        final_state: impl IntoIterator<Item = char>,
+        rng: &mut G,
+    ) -> Result<Turtle, RenderError>
+    where
+        G: Rng,
+    {
+        let mut turtle = self.new_turtle();
+        for c in final_state {
+            self.interpret_checked(&mut turtle, c, rng)?;
+        }
+        Ok(turtle)
+    }
+
+    /// Turtle after walking over `final_state`, like [`Self::turtle`], but ignoring
+    /// [`Self::max_stack_depth`].
+    fn trace<G>(&self, final_state: impl IntoIterator<Item = char>, rng: &mut G) -> Turtle
+    where
+        G: Rng,
+    {
+        let mut turtle = self.new_turtle();
+        for c in final_state {
+            self.interpret(&mut turtle, c, rng);
+        }
+        turtle
+    }
+
+    /// Turtle at [`Self::origin`], before it has moved.
+    fn new_turtle(&self) -> Turtle {
+        let mut turtle = Turtle::new(self.initial_heading);
+        turtle.position = self.origin;
+        turtle
+    }
+
+    /// Move `turtle` as `c` says to, like [`Self::interpret`], but failing instead of saving
+    /// another position while it is already inside [`Self::max_stack_depth`] branches, or if it
+    /// moves too far.
+    fn interpret_checked<G>(
+        &self,
+        turtle: &mut Turtle,
+        c: char,
+        rng: &mut G,
+    ) -> Result<(), RenderError>
+    where
+        G: Rng,
+    {
+        match self.max_stack_depth {
+            Some(limit) if c == '[' && turtle.stack.len() >= limit => {
+                return Err(RenderError::MaxDepthExceeded(limit));
+            }
+            _ => {}
+        }
+        self.interpret(turtle, c, rng);
+        if turtle.overflowed {
+            return Err(LSystemError::PositionOverflow.into());
+        }
+        Ok(())
+    }
+
+    /// Move `turtle` as `c` says to, like [`Self::turtle`].
+    ///
+    /// Steps that would move the turtle too far for its position to be represented are skipped.
+    fn interpret<G>(&self, turtle: &mut Turtle, c: char, rng: &mut G)
+    where
+        G: Rng,
+    {
+        let direction = if self.clockwise {
+            Decimal::ONE
+        } else {
+            Decimal::NEGATIVE_ONE
+        };
+        match c {
+            '+' => turtle.turn(direction * (self.angle + self.jitter(rng))),
+            '-' => turtle.turn(direction * (-self.angle + self.jitter(rng))),
+            '|' => turtle.reverse(),
+            '@' => turtle.reset_heading(),
+            '\'' | ';' => turtle.next_color(),
+            '!' => turtle.decrement_width(),
+            '.' => turtle.dot(),
+            '[' => turtle.push(),
+            ']' => turtle.pop(),
+            other if self.turn_angles.contains_key(&other) => {
+                turtle.turn(direction * self.turn_angles[&other]);
+            }
+            other if self.is_drawn(other) || self.variables_to_move.contains(&other) => {
+                let length = self.step_lengths.get(&other).copied();
+                turtle.forward(
+                    length.unwrap_or(Decimal::ONE),
+                    !self.is_drawn(other),
+                    Some(other),
+                );
+            }
+            _ => {}
+        }
+    }
+
+    /// Whether `c` means anything to the L-system: an operator, a letter that is drawn, moved
+    /// with or turned by, or a letter with a rule.
+    ///
+    /// Other letters are reported as [`LSystemError::MissingRule`].
+    fn is_defined(&self, c: char) -> bool {
+        is_operator(c)
+            || self.is_drawn(c)
+            || self.variables_to_move.contains(&c)
+            || self.turn_angles.contains_key(&c)
+            || self.rules.contains_key(&c)
+            || self.context_rules.iter().any(|rule| rule.variable == c)
+    }
+
+    /// Whether `c` is drawn as a stroke, from [`Self::variables_to_draw`] or [`Self::draw_all`].
+    fn is_drawn(&self, c: char) -> bool {
+        self.variables_to_draw.contains(&c)
+            || (self.draw_all
+                && !is_operator(c)
+                && !self.turn_angles.contains_key(&c)
+                && !self.variables_to_move.contains(&c))
+    }
+
+    /// Random change to a turn of up to [`Self::angle_jitter`] in either direction.
+    fn jitter(&self, rng: &mut impl Rng) -> Decimal {
+        if self.angle_jitter.is_zero() {
+            return Decimal::ZERO;
+        }
+        let steps = 1_000_000;
+        self.angle_jitter * Decimal::from(rng.gen_range(-steps..=steps)) / Decimal::from(steps)
+    }
+
+    /// Run the L-system and convert it into an SVG.
+    ///
+    /// Each color is drawn as its own path, starting with a move to the turtle's starting position,
+    /// unless [`SvgOptions::polylines`] is set.
+    pub fn to_svg<W>(&self, options: &SvgOptions, writer: W) -> Result<(), RenderError>
+    where
+        W: Write,
+    {
+        let turtle = self.walk_turtle(self.checked_symbols()?)?;
+        write_svg(&turtle, None, options, writer)
+    }
+
+    /// Run the L-system and convert it into an SVG, like [`Self::to_svg`], drawing random changes
+    /// to turns from `rng` instead of a generator seeded with [`Self::seed`].
+    pub fn to_svg_with_rng<W, G>(
+        &self,
+        options: &SvgOptions,
+        writer: W,
+        rng: &mut G,
+    ) -> Result<(), RenderError>
+    where
+        W: Write,
+        G: Rng,
+    {
+        let turtle = self.turtle(self.checked_symbols()?, rng)?;
+        write_svg(&turtle, None, options, writer)
+    }
+
+    /// Run the L-system and convert the characters of its final state from `start` up to `end`
+    /// into an SVG, like [`Self::to_svg`], to see part of a large drawing.
+    ///
+    /// The turtle still walks over the characters before `start` so that it begins where it
+    /// would in the full drawing, but only what it draws from `start` on is written and scaled to
+    /// fit the canvas. Ranges past the end of the final state are cut short.
+    pub fn to_svg_range<W>(
+        &self,
+        start: usize,
+        end: usize,
+        options: &SvgOptions,
+        writer: W,
+    ) -> Result<(), RenderError>
+    where
+        W: Write,
+    {
+        let mut symbols = self.checked_symbols()?.take(end);
+        let mut turtle = self.new_turtle();
+        let mut rng = ChaCha8Rng::seed_from_u64(self.seed);
+        for c in symbols.by_ref().take(start) {
+            self.interpret_checked(&mut turtle, c, &mut rng)?;
+        }
+        turtle.strokes.clear();
+        turtle.leaves.clear();
+        turtle.dots.clear();
+        for c in symbols {
+            self.interpret_checked(&mut turtle, c, &mut rng)?;
+        }
+        write_svg(&turtle, None, options, writer)
+    }
+
+    /// Run the L-system and convert it into an SVG, like [`Self::to_svg`], as a string.
+    pub fn to_svg_string(&self, options: &SvgOptions) -> Result<String, RenderError> {
+        let mut svg = vec![];
+        self.to_svg(options, &mut svg)?;
+        Ok(String::from_utf8(svg).expect("SVG is valid UTF-8"))
+    }
+
+    /// Run the L-system and convert it into an SVG, like [`Self::to_svg`], calling `progress`
+    /// before each iteration and every [`PROGRESS_INTERVAL`] characters walked by the turtle.
+    ///
+    /// Unlike [`Self::to_svg`], the final state is held in memory so that its length is known.
+    pub fn to_svg_with_progress<W, P>(
+        &self,
+        options: &SvgOptions,
+        writer: W,
+        progress: P,
+    ) -> Result<(), RenderError>
+    where
+        W: Write,
+        P: FnMut(Progress),
+    {
+        self.render_with(options, writer, progress, || false)
+    }
+
+    /// Run the L-system and convert it into an SVG, like [`Self::to_svg`], calling `should_cancel`
+    /// every [`PROGRESS_INTERVAL`] characters rewritten or walked by the turtle.
+    ///
+    /// Fails with [`RenderError::Cancelled`] as soon as `should_cancel` returns true, so a large
+    /// L-system can be abandoned partway through, e.g. with an [`AtomicBool`] set from another
+    /// thread.
+    ///
+    /// Like [`Self::to_svg_with_progress`], the final state is held in memory.
+    ///
+    /// [`AtomicBool`]: std::sync::atomic::AtomicBool
+    pub fn to_svg_cancellable<W, C>(
+        &self,
+        options: &SvgOptions,
+        writer: W,
+        should_cancel: C,
+    ) -> Result<(), RenderError>
+    where
+        W: Write,
+        C: Fn() -> bool,
+    {
+        self.render_with(options, writer, |_| {}, should_cancel)
+    }
+
+    /// Convert the L-system into an SVG, reporting `progress` before each iteration and every
+    /// [`PROGRESS_INTERVAL`] characters walked by the turtle, and checking `should_cancel` every
+    /// [`PROGRESS_INTERVAL`] characters rewritten or walked.
+    fn render_with<W, P, C>(
+        &self,
+        options: &SvgOptions,
+        writer: W,
+        mut progress: P,
+        should_cancel: C,
+    ) -> Result<(), RenderError>
+    where
+        W: Write,
+        P: FnMut(Progress),
+        C: Fn() -> bool,
+    {
+        let poll = || {
+            if should_cancel() {
+                Err(RenderError::Cancelled)
+            } else {
+                Ok(())
+            }
+        };
+        self.check_state_len()?;
+        let mut state = self.axiom.as_ref().to_string();
+        self.check_len(state.len())?;
+        for iteration in 1..=self.iterations {
+            progress(Progress::Expanding {
+                iteration,
+                iterations: self.iterations,
+            });
+            state = self.rewrite_with(&state, poll)?;
+            self.check_len(state.len())?;
+        }
+
+        let len = state.chars().count();
+        let mut turtle = self.new_turtle();
+        let mut rng = ChaCha8Rng::seed_from_u64(self.seed);
+        for (i, c) in state.chars().enumerate() {
+            if i % PROGRESS_INTERVAL == 0 {
+                poll()?;
+            }
+            self.interpret_checked(&mut turtle, c, &mut rng)?;
+            let walked = i + 1;
+            if walked % PROGRESS_INTERVAL == 0 || walked == len {
+                progress(Progress::Drawing {
+                    walked,
+                    len,
+                    strokes: turtle.strokes.len(),
+                });
+            }
+        }
+        write_svg(&turtle, None, options, writer)
+    }
+
+    /// Run the L-system for 0 up to [`Self::iterations`] iterations, converting each into an SVG
+    /// frame.
+    ///
+    /// Frames are scaled to the bounding box of the last one, so the drawing grows in place.
+    pub fn iteration_svgs(&self, options: &SvgOptions) -> Result<Vec<String>, RenderError> {
+        self.check_state_len()?;
+        let mut state = self.axiom.as_ref().to_string();
+        let mut frames = vec![self.walk_turtle(state.chars())?];
+        for _ in 0..self.iterations {
+            state = self.rewrite(&state);
+            self.check_len(state.len())?;
+            frames.push(self.walk_turtle(state.chars())?);
+        }
+
+        let bounds = frames
+            .last()
+            .and_then(|turtle| bounding_box(&turtle.strokes));
+        frames
+            .iter()
+            .map(|turtle| {
+                let mut svg = vec![];
+                write_svg(turtle, bounds, options, &mut svg)?;
+                Ok(String::from_utf8(svg).expect("SVG is valid UTF-8"))
+            })
+            .collect()
+    }
+
+    /// Run the L-system and rasterize it into a PNG using [cairo](https://www.cairographics.org/).
+    ///
+    /// [`SvgOptions::width`] and [`SvgOptions::height`] are the size of the image in pixels.
+    #[cfg(feature = "cairo")]
+    pub fn to_png<W>(&self, options: &SvgOptions, mut writer: W) -> Result<(), RenderError>
+    where
+        W: Write,
+    {
+        use rust_decimal::prelude::ToPrimitive;
+
+        let pixels = |size: Decimal| {
+            size.round()
+                .to_i32()
+                .ok_or(RenderError::InvalidImageSize(size))
+        };
+        let width = pixels(options.width)?;
+        let height = pixels(options.height)?;
+        let surface = cairo::ImageSurface::create(cairo::Format::ARgb32, width, height)?;
+        let context = cairo::Context::new(&surface)?;
+        self.draw_to_context(&context, options)?;
+        drop(context);
+
+        surface.write_to_png(&mut writer).map_err(|err| match err {
+            cairo::IoError::Io(err) => RenderError::Io(err),
+            cairo::IoError::Cairo(err) => RenderError::Cairo(err),
+        })
+    }
+
+    /// Run the L-system and stroke it onto an existing cairo context, to embed it in a larger
+    /// drawing.
+    ///
+    /// The drawing fills a [`SvgOptions::width`] x [`SvgOptions::height`] area at the origin of
+    /// the context's current transform, which is left as it was.
+    #[cfg(feature = "cairo")]
+    pub fn draw_to_context(
+        &self,
+        context: &cairo::Context,
+        options: &SvgOptions,
+    ) -> Result<(), RenderError> {
+        use rust_decimal::prelude::ToPrimitive;
+        use std::str::FromStr;
+
+        let symbols = self.checked_symbols()?;
+        let to_f64 = |value: Decimal| value.to_f64().expect("decimals fit in f64");
+        let (width, height) = (to_f64(options.width), to_f64(options.height));
+
+        context.save()?;
+        context.scale(width, height);
+        let line_width = match options.stroke_width {
+            Some(stroke_width) => to_f64(stroke_width),
+            // 1 unit of the context
+            None => 1. / width.min(height),
+        };
+        context.set_line_cap(match options.line_cap {
+            LineCap::Butt => cairo::LineCap::Butt,
+            LineCap::Round => cairo::LineCap::Round,
+            LineCap::Square => cairo::LineCap::Square,
+        });
+        context.set_line_join(match options.line_join {
+            LineJoin::Miter => cairo::LineJoin::Miter,
+            LineJoin::Round => cairo::LineJoin::Round,
+            LineJoin::Bevel => cairo::LineJoin::Bevel,
+        });
+        if let Some(shape_rendering) = options.shape_rendering {
+            context.set_antialias(match shape_rendering {
+                ShapeRendering::OptimizeSpeed => cairo::Antialias::Fast,
+                ShapeRendering::CrispEdges => cairo::Antialias::None,
+                ShapeRendering::GeometricPrecision => cairo::Antialias::Best,
+            });
+        }
+
+        let strokes = self
+            .walk(symbols)?
+            .into_iter()
+            .map(|stroke| Stroke {
+                start: options.flip(stroke.start),
+                end: options.flip(stroke.end),
+                ..stroke
+            })
+            .collect::<Vec<_>>();
+        let strokes = bounding_box(&strokes)
+            .map(|bounds| normalize(&strokes, bounds, options))
+            .unwrap_or_default();
+        for group in options.stroke_groups(&strokes) {
+            context.set_line_width(line_width * to_f64(group.width));
+            let rgba = svgtypes::Color::from_str(&group.color())
+                .unwrap_or_else(|_| svgtypes::Color::black());
+            context.set_source_rgba(
+                f64::from(rgba.red) / 255.,
+                f64::from(rgba.green) / 255.,
+                f64::from(rgba.blue) / 255.,
+                f64::from(rgba.alpha) / 255. * to_f64(group.opacity),
+            );
+            for (points, _) in
+                pen_down_runs(&strokes, |stroke| options.stroke_group(stroke) == group)
+            {
+                context.move_to(to_f64(points[0].0), to_f64(points[0].1));
+                for (x, y) in &points[1..] {
+                    context.line_to(to_f64(*x), to_f64(*y));
+                }
+            }
+            context.stroke()?;
+        }
+        context.restore()?;
+
+        Ok(())
+    }
+}
+
+/// Iterator for [`LSystem::symbols`].
+///
+/// Each level of the stack is a replacement being produced, with the axiom at the bottom.
+struct Symbols<'a, A: AsRef<str>, R: AsRef<str>> {
+    l_system: &'a LSystem<A, R>,
+    stack: Vec<std::str::Chars<'a>>,
+    /// Final state, if it had to be calculated up front.
+    expanded: Option<std::vec::IntoIter<char>>,
+}
+
+impl<'a, A, R> Iterator for Symbols<'a, A, R>
+where
+    A: AsRef<str>,
+    R: AsRef<str>,
+{
+    type Item = char;
+
+    fn next(&mut self) -> Option<char> {
+        if let Some(expanded) = &mut self.expanded {
+            return expanded.next();
+        }
+        loop {
+            let Some(c) = self.stack.last_mut()?.next() else {
+                self.stack.pop();
+                continue;
+            };
+            if self.stack.len() > self.l_system.iterations {
+                return Some(c);
+            }
+            match self.l_system.replacement(c) {
+                Some(replacement) => self.stack.push(replacement.chars()),
+                None => return Some(c),
+            }
+        }
+    }
+}
+
+/// Turtle that records [`Stroke`]s as it moves.
+struct Turtle {
+    position: (Decimal, Decimal),
+    heading: Decimal,
+    initial_heading: Decimal,
+    color: usize,
+    width: usize,
+    /// Index of the outermost branch the turtle is inside of.
+    branch: Option<usize>,
+    /// Number of outermost branches the turtle has entered.
+    branches: usize,
+    /// Position, heading and width to return to at the end of each branch.
+    stack: Vec<((Decimal, Decimal), Decimal, usize)>,
+    strokes: Vec<Stroke>,
+    /// Whether the last thing the turtle did in the current branch was draw a line.
+    at_tip: bool,
+    /// Positions at the tips of branches that ended after drawing a line.
+    leaves: Vec<(Decimal, Decimal)>,
+    /// Dots drawn with `.`, as strokes that start and end where they are, so they are colored
+    /// like the line they sit on.
+    dots: Vec<Stroke>,
+    /// Whether a step was skipped because the position would overflow.
+    overflowed: bool,
+}
+
+impl Turtle {
+    fn new(heading: Decimal) -> Self {
+        Self {
+            position: (Decimal::ZERO, Decimal::ZERO),
+            heading,
+            initial_heading: heading,
+            color: 0,
+            width: 0,
+            branch: None,
+            branches: 0,
+            stack: vec![],
+            strokes: vec![],
+            at_tip: false,
+            leaves: vec![],
+            dots: vec![],
+            overflowed: false,
+        }
+    }
+
+    fn turn(&mut self, angle: Decimal) {
+        self.heading += angle;
+    }
+
+    fn reverse(&mut self) {
+        self.heading += Decimal::PI;
+    }
+
+    fn reset_heading(&mut self) {
+        self.heading = self.initial_heading;
+    }
+
+    fn next_color(&mut self) {
+        self.color += 1;
+    }
+
+    fn decrement_width(&mut self) {
+        self.width += 1;
+    }
+
+    fn dot(&mut self) {
+        let variable = self
+            .strokes
+            .last()
+            .filter(|stroke| !stroke.is_move && stroke.end == self.position)
+            .and_then(|stroke| stroke.variable);
+        self.dots.push(Stroke {
+            start: self.position,
+            end: self.position,
+            is_move: false,
+            variable,
+            color: self.color,
+            width: self.width,
+            branch: self.branch,
+            depth: self.stack.len(),
+        });
+    }
+
+    fn push(&mut self) {
+        if self.stack.is_empty() {
+            self.branch = Some(self.branches);
+            self.branches += 1;
+        }
+        self.stack.push((self.position, self.heading, self.width));
+        self.at_tip = false;
+    }
+
+    fn pop(&mut self) {
+        // A `]` without a matching `[` is ignored
+        if let Some((position, heading, width)) = self.stack.pop() {
+            if self.at_tip {
+                self.leaves.push(self.position);
+            }
+            self.at_tip = false;
+            self.strokes.push(Stroke {
+                start: self.position,
+                end: position,
+                is_move: true,
+                variable: None,
+                color: self.color,
+                width: self.width,
+                branch: self.branch,
+                depth: self.stack.len(),
+            });
+            self.position = position;
+            self.heading = heading;
+            self.width = width;
+            if self.stack.is_empty() {
+                self.branch = None;
+            }
+        }
+    }
+
+    fn forward(&mut self, length: Decimal, is_move: bool, variable: Option<char>) {
+        let next_position = self
+            .heading
+            .cos()
+            .checked_mul(length)
+            .and_then(|dx| self.position.0.checked_add(dx))
+            .zip(
+                self.heading
+                    .sin()
+                    .checked_mul(length)
+                    .and_then(|dy| self.position.1.checked_add(dy)),
+            );
+        let Some(next_position) = next_position else {
+            self.overflowed = true;
+            return;
+        };
+        self.strokes.push(Stroke {
+            start: self.position,
+            end: next_position,
+            is_move,
+            variable,
+            color: self.color,
+            width: self.width,
+            branch: self.branch,
+            depth: self.stack.len(),
+        });
+        self.position = next_position;
+        self.at_tip = !is_move;
+    }
+}
+
+/// Write the strokes of `turtle` as an SVG, scaled to `bounds` or their own bounding box, with
+/// its dots and markers at its leaves.
+fn write_svg<W>(
+    turtle: &Turtle,
+    bounds: Option<(Decimal, Decimal, Decimal, Decimal)>,
+    options: &SvgOptions,
+    writer: W,
+) -> Result<(), RenderError>
+where
+    W: Write,
+{
+    write_drawings(std::slice::from_ref(turtle), false, bounds, options, writer)
+}
+
+/// Write the strokes of each turtle in `turtles` into one SVG like [`write_svg`], scaled to
+/// `bounds` or the bounding box of all of them, wrapping each in a `<g>` if `groups` is set.
+fn write_drawings<W>(
+    turtles: &[Turtle],
+    groups: bool,
+    bounds: Option<(Decimal, Decimal, Decimal, Decimal)>,
+    options: &SvgOptions,
+    mut writer: W,
+) -> Result<(), RenderError>
+where
+    W: Write,
+{
+    let SvgOptions {
+        width,
+        height,
+        units,
+        ..
+    } = options;
+    // Numbers are written without trailing zeros throughout
+    let (width, height) = (&width.normalize(), &height.normalize());
+    let units = length_unit_suffix(*units);
+    // Nothing would be left of the canvas to draw on
+    if options.margin.is_sign_negative() || options.margin >= Decimal::new(5, 1) {
+        return Err(RenderError::InvalidMargin(options.margin));
+    }
+    if options.xml_declaration {
+        writeln!(writer, r#"<?xml version="1.0" encoding="UTF-8"?>"#)?;
+    }
+
+    let scale = |point: (Decimal, Decimal)| {
+        let (x, y) = options.flip(point);
+        match options.unit_length {
+            Some(unit_length) => (x * unit_length, y * unit_length),
+            None => (x, y),
+        }
+    };
+    // Strokes, leaves and dots of each turtle
+    let drawings = turtles
+        .iter()
+        .map(|turtle| {
+            let strokes = if options.collapse_collinear {
+                collapse_collinear(&turtle.strokes, options)
+            } else {
+                turtle.strokes.clone()
+            };
+            let strokes = strokes
+                .iter()
+                .map(|stroke| Stroke {
+                    start: scale(stroke.start),
+                    end: scale(stroke.end),
+                    ..*stroke
+                })
+                .collect::<Vec<_>>();
+            let leaves = turtle.leaves.iter().copied().map(scale).collect::<Vec<_>>();
+            let dots = turtle
+                .dots
+                .iter()
+                .map(|dot| Stroke {
+                    start: scale(dot.start),
+                    end: scale(dot.end),
+                    ..*dot
+                })
+                .collect::<Vec<_>>();
+            (strokes, leaves, dots)
+        })
+        .collect::<Vec<_>>();
+    let bounds = bounds.map(|(min_x, min_y, max_x, max_y)| {
+        let (min_x, min_y) = scale((min_x, min_y));
+        let (max_x, max_y) = scale((max_x, max_y));
+        // Flipping swaps the ends of the y axis
+        (min_x, min_y.min(max_y), max_x, min_y.max(max_y))
+    });
+
+    // Transform of normalized drawings
+    let content_scale = options
+        .content_scale
+        .map_or((*width, *height), |(x, y)| (x.normalize(), y.normalize()));
+    let content_offset = (
+        options.content_offset.0.normalize(),
+        options.content_offset.1.normalize(),
+    );
+
+    let bounds = bounds.or_else(|| {
+        drawings
+            .iter()
+            .filter_map(|(strokes, _, _)| bounding_box(strokes))
+            .reduce(|a, b| (a.0.min(b.0), a.1.min(b.1), a.2.max(b.2), a.3.max(b.3)))
+    });
+    /// Places a point of the drawing in the SVG.
+    type Place = Box<dyn Fn((Decimal, Decimal)) -> (Decimal, Decimal)>;
+    let (place, view_box, transform, (width, height), unit): (Place, _, _, _, _) = match bounds {
+        Some((min_x, min_y, max_x, max_y)) if options.tight_view_box || !options.normalize => {
+            let range = (nonzero(max_x - min_x), nonzero(max_y - min_y));
+            let padding = |range: Decimal| {
+                range * options.margin / (Decimal::ONE - options.margin * Decimal::TWO)
+            };
+            let padding = (padding(range.0), padding(range.1));
+            let view_box = (
+                min_x - padding.0,
+                min_y - padding.1,
+                range.0 + padding.0 * Decimal::TWO,
+                range.1 + padding.1 * Decimal::TWO,
+            );
+            let precision = options.coordinate_precision;
+            let round = move |(x, y): (Decimal, Decimal)| {
+                (
+                    x.round_dp(precision).normalize(),
+                    y.round_dp(precision).normalize(),
+                )
+            };
+            let canvas = match options.unit_length {
+                Some(_) => (
+                    view_box
+                        .2
+                        .round_dp(options.coordinate_precision)
+                        .normalize(),
+                    view_box
+                        .3
+                        .round_dp(options.coordinate_precision)
+                        .normalize(),
+                ),
+                None => (*width, *height),
+            };
+            (
+                Box::new(round),
+                format!(
+                    "{} {} {} {}",
+                    view_box
+                        .0
+                        .round_dp(options.coordinate_precision)
+                        .normalize(),
+                    view_box
+                        .1
+                        .round_dp(options.coordinate_precision)
+                        .normalize(),
+                    view_box
+                        .2
+                        .round_dp(options.coordinate_precision)
+                        .normalize(),
+                    view_box
+                        .3
+                        .round_dp(options.coordinate_precision)
+                        .normalize()
+                ),
+                String::new(),
+                canvas,
+                (view_box.2 / nonzero(canvas.0)).max(view_box.3 / nonzero(canvas.1)),
+            )
+        }
+        Some((min_x, min_y, _, _)) if options.unit_length.is_some() => {
+            let offset = (
+                *width * options.margin - min_x,
+                *height * options.margin - min_y,
+            );
+            let precision = options.coordinate_precision;
+            let place = move |(x, y): (Decimal, Decimal)| {
+                (
+                    (x + offset.0).round_dp(precision).normalize(),
+                    (y + offset.1).round_dp(precision).normalize(),
+                )
+            };
+            (
+                Box::new(place),
+                format!("0 0 {width} {height}"),
+                String::new(),
+                (*width, *height),
+                Decimal::ONE,
+            )
+        }
+        _ => (
+            match bounds {
+                Some(bounds) => Box::new(normalizer(bounds, options)),
+                // There is nothing to place
+                None => Box::new(|point| point),
+            },
+            format!("0 0 {width} {height}"),
+            format!(
+                r#" transform="matrix({}, 0, 0, {}, {}, {})""#,
+                content_scale.0, content_scale.1, content_offset.0, content_offset.1
+            ),
+            (*width, *height),
+            Decimal::ONE / nonzero(content_scale.0.min(content_scale.1)),
+        ),
+    };
+
+    let drawings = drawings
+        .into_iter()
+        .map(|(strokes, leaves, dots)| {
+            let strokes = strokes
+                .iter()
+                .map(|stroke| Stroke {
+                    start: place(stroke.start),
+                    end: place(stroke.end),
+                    ..*stroke
+                })
+                .collect::<Vec<_>>();
+            let leaves = leaves.into_iter().map(&place).collect::<Vec<_>>();
+            let dots = dots
+                .iter()
+                .map(|dot| Stroke {
+                    start: place(dot.start),
+                    end: place(dot.end),
+                    ..*dot
+                })
+                .collect::<Vec<_>>();
+            (strokes, leaves, dots)
+        })
+        .collect::<Vec<_>>();
+
+    // The viewBox is stretched over the canvas like the normalized drawing would be
+    let aspect = if options.tight_view_box && options.normalize && !options.preserve_aspect_ratio {
+        r#" preserveAspectRatio="none""#
+    } else {
+        ""
+    };
+    writeln!(
+        writer,
+        r#"<svg xmlns="http://www.w3.org/2000/svg" xmlns:xlink="http://www.w3.org/1999/xlink" width="{width}{units}" height="{height}{units}" viewBox="{view_box}"{aspect}>"#
+    )?;
+
+    if let Some(title) = &options.title {
+        writeln!(writer, "<title>{}</title>", xml_escape(title))?;
+    }
+    if let Some(description) = &options.description {
+        writeln!(writer, "<desc>{}</desc>", xml_escape(description))?;
+    }
+
+    if options.adaptive_stroke_color {
+        writeln!(
+            writer,
+            "<style>\
+            @media (prefers-color-scheme: light) {{ .{ADAPTIVE_STROKE_CLASS} {{ stroke: black; }} }}\
+            @media (prefers-color-scheme: dark) {{ .{ADAPTIVE_STROKE_CLASS} {{ stroke: white; }} }}\
+            </style>"
+        )?;
+    }
+
+    // 1 unit
+    let stroke_width = options.stroke_width.unwrap_or(unit).normalize();
+    for (strokes, leaves, dots) in &drawings {
+        if groups {
+            writeln!(writer, "<g>")?;
+        }
+        if !strokes.is_empty() {
+            if options.branch_groups {
+                write_branch_groups(&mut writer, strokes, stroke_width, &transform, options)?;
+            } else {
+                write_paths(&mut writer, strokes, stroke_width, &transform, options)?;
+            }
+
+            for &(x, y) in leaves {
+                if let Some(radius) = options.leaf_dot_radius.map(|radius| radius.normalize()) {
+                    writeln!(
+                        writer,
+                        r#"<circle cx="{x}" cy="{y}" r="{radius}" fill="{}"{transform}/>"#,
+                        options.theme.stroke_color()
+                    )?;
+                }
+                if let Some(marker) = &options.leaf_marker {
+                    // Normalized drawings are placed on the canvas by their transform
+                    let (x, y) = if transform.is_empty() {
+                        (x, y)
+                    } else {
+                        (
+                            (x * content_scale.0 + content_offset.0).normalize(),
+                            (y * content_scale.1 + content_offset.1).normalize(),
+                        )
+                    };
+                    writeln!(writer, r#"<g transform="translate({x} {y})">{marker}</g>"#)?;
+                }
+            }
+        }
+
+        // Dots are drawn even if no lines are
+        let dot_radius = options
+            .dot_radius
+            .map_or(stroke_width, |radius| radius.normalize());
+        for dot in dots {
+            let (x, y) = dot.start;
+            writeln!(
+                writer,
+                r#"<circle cx="{x}" cy="{y}" r="{dot_radius}" fill="{}"{transform}/>"#,
+                xml_escape(&options.stroke_group(dot).color())
+            )?;
+        }
+        if groups {
+            writeln!(writer, "</g>")?;
+        }
+    }
+
+    writeln!(writer, "</svg>")?;
+
+    Ok(())
+}
+
+/// Write a `<g>` for each branch (`[...]`) of `strokes`, nested like the branches are, around
+/// the paths drawn inside of it.
+fn write_branch_groups<W>(
+    mut writer: W,
+    strokes: &[Stroke],
+    stroke_width: Decimal,
+    transform: &str,
+    options: &SvgOptions,
+) -> std::io::Result<()>
+where
+    W: Write,
+{
+    let mut depth = 0;
+    let mut start = 0;
+    // Leaving a branch moves the turtle back at the outer depth, which closes the group
+    for (i, stroke) in strokes.iter().enumerate() {
+        if stroke.depth == depth {
+            continue;
+        }
+        write_paths(
+            &mut writer,
+            &strokes[start..i],
+            stroke_width,
+            transform,
+            options,
+        )?;
+        start = i;
+        while depth < stroke.depth {
+            writeln!(writer, "<g>")?;
+            depth += 1;
+        }
+        while depth > stroke.depth {
+            writeln!(writer, "</g>")?;
+            depth -= 1;
+        }
+    }
+    write_paths(
+        &mut writer,
+        &strokes[start..],
+        stroke_width,
+        transform,
+        options,
+    )?;
+    for _ in 0..depth {
+        writeln!(writer, "</g>")?;
+    }
+    Ok(())
+}
+
+/// Write `strokes` as a path for each color and width.
+fn write_paths<W>(
+    mut writer: W,
+    strokes: &[Stroke],
+    stroke_width: Decimal,
+    transform: &str,
+    options: &SvgOptions,
+) -> std::io::Result<()>
+where
+    W: Write,
+{
+    let line_cap = options.line_cap.as_str();
+    let line_join = options.line_join.as_str();
+    for group in options.stroke_groups(strokes) {
+        let stroke_width = stroke_width.saturating_mul(group.width).normalize();
+        let opacity = group.opacity.normalize();
+        let color = group.color();
+        // CSS takes precedence over the stroke attribute
+        let class = if options.adaptive_stroke_color
+            && options.stroke
+            && color == options.theme.stroke_color()
+        {
+            format!(r#" class="{ADAPTIVE_STROKE_CLASS}""#)
+        } else {
+            String::new()
+        };
+        let fill = match &options.fill {
+            Some(fill) => format!(
+                r#"fill="{}" fill-rule="{}""#,
+                xml_escape(fill),
+                options.fill_rule.as_str()
+            ),
+            None => r#"fill="none""#.to_string(),
+        };
+        let shape_rendering = match options.shape_rendering {
+            Some(shape_rendering) => {
+                format!(r#" shape-rendering="{}""#, shape_rendering.as_str())
+            }
+            None => String::new(),
+        };
+        let stroke = xml_escape(if options.stroke { &color } else { "none" });
+        let attributes = format!(
+            r#"{class} {fill} stroke-width="{stroke_width}" stroke-linecap="{line_cap}" stroke-linejoin="{line_join}" stroke="{stroke}" stroke-opacity="{opacity}" stroke-miterlimit="10"{shape_rendering}"#
+        );
+
+        if options.polylines {
+            let runs = pen_down_runs(strokes, |stroke| options.stroke_group(stroke) == group);
+            for (points, length) in runs {
+                let points = points
+                    .iter()
+                    .map(|(x, y)| format!("{x},{y}"))
+                    .collect::<Vec<_>>()
+                    .join(" ");
+                write!(writer, r#"<polyline{attributes} points="{points}""#)?;
+                write_element_end(&mut writer, "polyline", transform, length, options)?;
+            }
+            continue;
+        }
+
+        write!(writer, r#"<path{attributes} d=""#)?;
+
+        // Start where the turtle does, so the first segment is drawn as well
+        let (start_x, start_y) = strokes[0].start;
+        write!(writer, "M {start_x} {start_y}",)?;
+        let mut pen = strokes[0].start;
+        let mut subpath_start = None;
+        // Segments of other colors are skipped, so the pen may need to be moved back
+        let mut pen_at_start = true;
+        let mut length = Decimal::ZERO;
+        for stroke in strokes {
+            if stroke.is_move {
+                close_subpath(&mut writer, &mut pen, &mut subpath_start, options)?;
+                write_path_command(&mut writer, 'M', stroke.end, &mut pen, options)?;
+                pen_at_start = true;
+            } else if options.stroke_group(stroke) == group {
+                if !pen_at_start {
+                    close_subpath(&mut writer, &mut pen, &mut subpath_start, options)?;
+                    write_path_command(&mut writer, 'M', stroke.start, &mut pen, options)?;
+                }
+                subpath_start.get_or_insert(pen);
+                write_path_command(&mut writer, 'L', stroke.end, &mut pen, options)?;
+                pen_at_start = true;
+                length += distance(stroke.start, stroke.end);
+            } else {
+                pen_at_start = false;
+            }
+        }
+        close_subpath(&mut writer, &mut pen, &mut subpath_start, options)?;
+        write!(writer, "\"")?;
+        write_element_end(&mut writer, "path", transform, length, options)?;
+    }
+
+    Ok(())
+}
+
+/// Write a path command to `point`, relative to `pen` with [`SvgOptions::relative_commands`],
+/// and move the pen there.
+fn write_path_command<W>(
+    mut writer: W,
+    command: char,
+    point: (Decimal, Decimal),
+    pen: &mut (Decimal, Decimal),
+    options: &SvgOptions,
+) -> std::io::Result<()>
+where
+    W: Write,
+{
+    if options.relative_commands {
+        // The pen follows the rounded deltas, so rounding errors do not accumulate
+        let precision = options.coordinate_precision;
+        let (dx, dy) = (
+            (point.0 - pen.0).round_dp(precision).normalize(),
+            (point.1 - pen.1).round_dp(precision).normalize(),
+        );
+        *pen = (pen.0 + dx, pen.1 + dy);
+        write!(writer, " {} {dx} {dy}", command.to_ascii_lowercase())
+    } else {
+        *pen = point;
+        write!(writer, " {command} {} {}", point.0, point.1)
+    }
+}
+
+/// Close a subpath that has drawn lines from `subpath_start` with `Z` when it is filled, which
+/// moves the pen back to its start.
+fn close_subpath<W>(
+    mut writer: W,
+    pen: &mut (Decimal, Decimal),
+    subpath_start: &mut Option<(Decimal, Decimal)>,
+    options: &SvgOptions,
+) -> std::io::Result<()>
+where
+    W: Write,
+{
+    if let Some(start) = subpath_start.take() {
+        if options.fill.is_some() {
+            *pen = start;
+            write!(writer, " Z")?;
+        }
+    }
+    Ok(())
+}
+
+/// Finish an element of `length` that was opened with its attributes, animating it if needed.
+fn write_element_end<W>(
+    mut writer: W,
+    element: &str,
+    transform: &str,
+    length: Decimal,
+    options: &SvgOptions,
+) -> std::io::Result<()>
+where
+    W: Write,
+{
+    match options.animation_duration {
+        Some(duration) => {
+            let duration = duration.normalize();
+            let length = length.round_dp(options.coordinate_precision).normalize();
+            writeln!(
+                writer,
+                r#"{transform} stroke-dasharray="{length}" stroke-dashoffset="{length}"><animate attributeName="stroke-dashoffset" from="{length}" to="0" dur="{duration}s" fill="freeze"/></{element}>"#
+            )
+        }
+        None => writeln!(writer, "{transform}/>"),
+    }
+}
+
+/// Merge consecutive drawn strokes of the same color that continue in the same direction.
+fn collapse_collinear(strokes: &[Stroke], options: &SvgOptions) -> Vec<Stroke> {
+    // Headings are only as accurate as the trigonometry behind them
+    let tolerance = Decimal::new(1, 10);
+    let mut collapsed: Vec<Stroke> = Vec::with_capacity(strokes.len());
+    for stroke in strokes {
+        if let Some(last) = collapsed.last_mut() {
+            let (last_x, last_y) = (last.end.0 - last.start.0, last.end.1 - last.start.1);
+            let (x, y) = (stroke.end.0 - stroke.start.0, stroke.end.1 - stroke.start.1);
+            if !last.is_move
+                && !stroke.is_move
+                && last.end == stroke.start
+                && options.stroke_group(last) == options.stroke_group(stroke)
+                && (last_x * y - last_y * x).abs() <= tolerance
+                && last_x * x + last_y * y > Decimal::ZERO
+            {
+                last.end = stroke.end;
+                continue;
+            }
+        }
+        collapsed.push(*stroke);
+    }
+    collapsed
+}
+
+/// Points of each continuous run of drawn strokes that are `included`, along with its length.
+fn pen_down_runs(
+    strokes: &[Stroke],
+    included: impl Fn(&Stroke) -> bool,
+) -> Vec<(Vec<(Decimal, Decimal)>, Decimal)> {
+    let mut runs: Vec<(Vec<(Decimal, Decimal)>, Decimal)> = vec![];
+    let mut pen_at_start = false;
+    for stroke in strokes {
+        if stroke.is_move || !included(stroke) {
+            pen_at_start = false;
+            continue;
+        }
+        let length = distance(stroke.start, stroke.end);
+        match runs.last_mut() {
+            Some((points, run_length)) if pen_at_start => {
+                points.push(stroke.end);
+                *run_length += length;
+            }
+            _ => runs.push((vec![stroke.start, stroke.end], length)),
+        }
+        pen_at_start = true;
+    }
+    runs
+}
+
+/// Escape `s` so that it can be used as XML text or in an attribute value.
+fn xml_escape(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+fn degrees_to_radians(degrees: Decimal) -> Decimal {
+    degrees / Decimal::from(180) * Decimal::PI
+}
+
+fn radians_to_degrees(radians: Decimal) -> Decimal {
+    radians / Decimal::PI * Decimal::from(180)
+}
+
+/// Color of the outermost branch with index `branch`, for [`SvgOptions::branch_colorize`].
+fn branch_color(branch: usize) -> String {
+    // Golden angle of 137.5°, in tenths of a degree
+    let hue = Decimal::new((branch % 144 * 1375 % 3600) as i64, 1).normalize();
+    format!("hsl({hue}, 65%, 45%)")
+}
+
+/// Distance between two points.
+fn distance(start: (Decimal, Decimal), end: (Decimal, Decimal)) -> Decimal {
+    let (dx, dy) = (end.0 - start.0, end.1 - start.1);
+    (dx * dx + dy * dy)
+        .sqrt()
+        .expect("sum of squares is not negative")
+}
+
+/// Bounds of the points strokes start and end at, as `(min_x, min_y, max_x, max_y)`.
+fn bounding_box(strokes: &[Stroke]) -> Option<(Decimal, Decimal, Decimal, Decimal)> {
+    let points = || strokes.iter().flat_map(|stroke| [stroke.start, stroke.end]);
+    Some((
+        points().map(|point| point.0).min()?,
+        points().map(|point| point.1).min()?,
+        points().map(|point| point.0).max()?,
+        points().map(|point| point.1).max()?,
+    ))
+}
+
+/// Substitute for the range of an axis that has no extent, like that of a straight line.
+fn nonzero(range: Decimal) -> Decimal {
+    if range.is_zero() {
+        Decimal::ONE
+    } else {
+        range
+    }
+}
+
+/// Scale strokes so that `bounds` fits within `[margin, 1 - margin]` on both axes.
+#[cfg(feature = "cairo")]
+fn normalize(
+    strokes: &[Stroke],
+    bounds: (Decimal, Decimal, Decimal, Decimal),
+    options: &SvgOptions,
+) -> Vec<Stroke> {
+    let normalize = normalizer(bounds, options);
+    strokes
+        .iter()
+        .map(|stroke| Stroke {
+            start: normalize(stroke.start),
+            end: normalize(stroke.end),
+            ..*stroke
+        })
+        .collect()
+}
+
+/// Scale a point so that `bounds` fits within `[margin, 1 - margin]` on both axes.
+fn normalizer(
+    (min_x, min_y, max_x, max_y): (Decimal, Decimal, Decimal, Decimal),
+    options: &SvgOptions,
+) -> impl Fn((Decimal, Decimal)) -> (Decimal, Decimal) {
+    let (range, offset) = if options.preserve_aspect_ratio {
+        // The larger range on the canvas determines the scale of both axes
+        let range_x =
+            (max_x - min_x).max((max_y - min_y) * options.width / nonzero(options.height));
+        let range_y =
+            (max_y - min_y).max((max_x - min_x) * options.height / nonzero(options.width));
+        let range = (nonzero(range_x), nonzero(range_y));
+        let center = |min: Decimal, max: Decimal, range: Decimal| {
+            (Decimal::ONE - (max - min) / range) / Decimal::TWO
+        };
+        (
+            range,
+            (center(min_x, max_x, range.0), center(min_y, max_y, range.1)),
+        )
+    } else {
+        (
+            (nonzero(max_x - min_x), nonzero(max_y - min_y)),
+            (Decimal::ZERO, Decimal::ZERO),
+        )
+    };
+    let margin = options.margin;
+    let extent = Decimal::ONE - margin * Decimal::TWO;
+    let precision = options.coordinate_precision;
+    move |(x, y): (Decimal, Decimal)| {
+        (
+            (margin + ((x - min_x) / range.0 + offset.0) * extent)
+                .round_dp(precision)
+                .normalize(),
+            (margin + ((y - min_y) / range.1 + offset.1) * extent)
+                .round_dp(precision)
+                .normalize(),
+        )
+    }
+}
+
+/// Class of paths drawn in the color of [`SvgOptions::theme`] with
+/// [`SvgOptions::adaptive_stroke_color`].
+const ADAPTIVE_STROKE_CLASS: &str = "lsys-adaptive-stroke";
+
+/// Number of characters rewritten or walked by the turtle between calls to the callbacks of
+/// [`LSystem::to_svg_with_progress`] and [`LSystem::to_svg_cancellable`].
+pub const PROGRESS_INTERVAL: usize = 1 << 12;
+
+/// Number of bytes rewritten by each parallel task, and the shortest state worth splitting.
+#[cfg(feature = "parallel")]
+const PARALLEL_CHUNK_LEN: usize = 1 << 14;
+
+/// Strokes that are drawn the same way, as one path.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct StrokeGroup<'a> {
+    /// Color from [`SvgOptions::stroke_color`].
+    color: &'a str,
+    /// Factor that the stroke width is multiplied by, from [`SvgOptions::width_palette`] and
+    /// [`SvgOptions::width_taper`].
+    width: Decimal,
+    /// Opacity from [`SvgOptions::stroke_opacity`] and [`SvgOptions::depth_opacity_fade`].
+    opacity: Decimal,
+    /// Outermost branch, if [`SvgOptions::branch_colorize`] colors it with [`branch_color`]
+    /// instead.
+    branch: Option<usize>,
+}
+
+impl StrokeGroup<'_> {
+    /// Color that the strokes are drawn with.
+    fn color(&self) -> Cow<'_, str> {
+        match self.branch {
+            Some(branch) => Cow::Owned(branch_color(branch)),
+            None => Cow::Borrowed(self.color),
+        }
+    }
+}
+
+impl SvgOptions {
+    /// `point` with its y coordinate negated if [`Self::flip_y`] is set.
+    fn flip(&self, (x, y): (Decimal, Decimal)) -> (Decimal, Decimal) {
+        if self.flip_y {
+            (x, -y)
+        } else {
+            (x, y)
+        }
+    }
+
+    /// Color that `stroke` is drawn with.
+    fn stroke_color(&self, stroke: &Stroke) -> &str {
+        if self.palette.is_empty() {
+            stroke
+                .variable
+                .and_then(|variable| self.variable_colors.get(&variable))
+                .map(String::as_str)
+                .unwrap_or(self.theme.stroke_color())
+        } else {
+            self.palette[stroke.color % self.palette.len()].as_str()
+        }
+    }
+
+    /// How `stroke` is drawn.
+    fn stroke_group(&self, stroke: &Stroke) -> StrokeGroup<'_> {
+        let mut width = match self.width_palette.len() {
+            0 => Decimal::ONE,
+            len => self.width_palette[stroke.width.min(len - 1)],
+        };
+        // Factors greater than 1 overflow once branches nest deeply enough, so they saturate
+        let factor = |base: Decimal| {
+            base.checked_powu(stroke.depth as u64)
+                .unwrap_or(Decimal::MAX)
+        };
+        if self.width_taper != Decimal::ONE {
+            width = width.saturating_mul(factor(self.width_taper));
+        }
+        let opacity = match self.depth_opacity_fade {
+            Some(fade) => self.stroke_opacity.saturating_mul(factor(fade)),
+            None => self.stroke_opacity,
+        };
+        StrokeGroup {
+            color: self.stroke_color(stroke),
+            width,
+            opacity,
+            branch: stroke.branch.filter(|_| self.branch_colorize),
+        }
+    }
+
+    /// Distinct groups of drawn `strokes` that are drawn as one path, in order of appearance.
+    fn stroke_groups(&self, strokes: &[Stroke]) -> Vec<StrokeGroup<'_>> {
+        let mut groups: Vec<StrokeGroup<'_>> = vec![];
+        for group in strokes
+            .iter()
+            .filter(|stroke| !stroke.is_move)
+            .map(|stroke| self.stroke_group(stroke))
+        {
+            if !groups.contains(&group) {
+                groups.push(group);
+            }
+        }
+        groups
+    }
+}
+
+impl LSystem<String, String> {
+    /// Start building an L-system with [`LSystemBuilder`].
+    pub fn builder() -> LSystemBuilder {
+        LSystemBuilder::default()
+    }
+}
+
+/// Builder for an [`LSystem`] that validates its rules.
+#[derive(Debug, Clone, Default)]
+pub struct LSystemBuilder {
+    axiom: String,
+    variables_to_draw: HashSet<char>,
+    draw_all: bool,
+    angle: Decimal,
+    iterations: usize,
+    rules: HashMap<char, String>,
+}
+
+impl LSystemBuilder {
+    /// Initial string.
+    pub fn axiom(mut self, axiom: impl Into<String>) -> Self {
+        self.axiom = axiom.into();
+        self
+    }
+
+    /// Add a rule replacing `variable` with `replacement`.
+    pub fn rule(mut self, variable: char, replacement: impl Into<String>) -> Self {
+        self.rules.insert(variable, replacement.into());
+        self
+    }
+
+    /// Treat `variable` as a stroke and draw it.
+    pub fn draw(mut self, variable: char) -> Self {
+        self.variables_to_draw.insert(variable);
+        self
+    }
+
+    /// Draw every letter as a stroke, instead of choosing them with [`Self::draw`].
+    pub fn draw_all(mut self) -> Self {
+        self.draw_all = true;
+        self
+    }
+
+    /// Turn angle in radians.
+    pub fn angle(mut self, radians: Decimal) -> Self {
+        self.angle = radians;
+        self
+    }
+
+    /// Turn angle in degrees.
+    pub fn angle_degrees(mut self, degrees: impl Into<Decimal>) -> Self {
+        self.angle = degrees_to_radians(degrees.into());
+        self
+    }
+
+    /// Number of times the rules will run.
+    pub fn iterations(mut self, iterations: usize) -> Self {
+        self.iterations = iterations;
+        self
+    }
+
+    /// Build the L-system, checking that every letter in the axiom and rules has a rule.
+    pub fn build(self) -> Result<LSystem<String, String>, LSystemError> {
+        // Check the rules in order so the same missing rule is always reported
+        let mut rules = self.rules.iter().collect::<Vec<_>>();
+        rules.sort_unstable_by_key(|(variable, _)| **variable);
+        if let Some(missing) = self
+            .axiom
+            .chars()
+            .chain(rules.iter().flat_map(|(_, r)| r.chars()))
+            .find(|c| !is_operator(*c) && !self.rules.contains_key(c))
+        {
+            return Err(LSystemError::MissingRule(missing));
+        }
+
+        Ok(LSystem {
+            axiom: self.axiom,
+            variables_to_draw: self.variables_to_draw,
+            draw_all: self.draw_all,
+            angle: self.angle,
+            iterations: self.iterations,
+            rules: self.rules,
+            ..Default::default()
+        })
+    }
 }
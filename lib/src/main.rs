@@ -1,11 +1,12 @@
 use clap::Parser;
+use lsys::Example;
 use lsys::LSystem;
+use lsys::Progress;
 use lsys::SvgOptions;
-use rust_decimal::prelude::FromPrimitive;
 use rust_decimal::Decimal;
-use std::collections::HashMap;
 use std::collections::HashSet;
 use std::fs::File;
+use std::io::Read;
 use std::io::Write;
 use std::iter::FromIterator;
 use std::path::PathBuf;
@@ -15,16 +16,71 @@ use svgtypes::LengthUnit;
 #[command(version, about)]
 struct Args {
     /// Initial string.
-    axiom: String,
+    #[arg(required_unless_present = "grammar")]
+    axiom: Option<String>,
     /// Variables that should be treated as a stroke and drawn.
-    variables_to_draw: String,
+    #[arg(required_unless_present = "grammar")]
+    variables_to_draw: Option<String>,
     /// Turn angle in degrees.
-    angle: Decimal,
+    #[arg(required_unless_present = "grammar")]
+    angle: Option<Decimal>,
     /// Number of times the rules will run.
-    iterations: usize,
+    #[arg(required_unless_present = "grammar")]
+    iterations: Option<usize>,
     /// Rules for replacing characters with a new string (i.e. "F=>F+F").
     rules: Vec<String>,
 
+    /// Read the L-system from a file instead of the arguments above.
+    ///
+    /// The first line is the axiom, followed by `draw:`, `angle:` and `iterations:` headers and
+    /// one rule per line.
+    #[arg(long, value_name = "PATH", group = "grammar", conflicts_with_all = ["axiom", "rules"])]
+    file: Option<PathBuf>,
+    /// Read the L-system from standard input instead of the arguments above.
+    ///
+    /// The input is one line of the form `axiom|variables|rules|angle|iterations`, with rules
+    /// separated by commas (i.e. "F|F|F=>F+F-F-F+F|90|4"). Since `|` separates the fields, the
+    /// turn around operator cannot be used; use --file for grammars that need it.
+    #[arg(long, group = "grammar", conflicts_with_all = ["axiom", "rules"])]
+    stdin: bool,
+    /// Read the L-system from a JSON file instead of the arguments above.
+    ///
+    /// Fields are named as in the library, with angles in radians.
+    #[cfg(feature = "serde")]
+    #[arg(long, value_name = "PATH", group = "grammar", conflicts_with_all = ["axiom", "rules"])]
+    json: Option<PathBuf>,
+    /// Render each L-system in a JSON array of named L-systems into --out-dir.
+    ///
+    /// Each element has a `name` along with the fields read by --json.
+    #[cfg(feature = "serde")]
+    #[arg(
+        long,
+        value_name = "PATH",
+        group = "grammar",
+        requires = "out_dir",
+        conflicts_with_all = ["axiom", "rules", "out"]
+    )]
+    batch: Option<PathBuf>,
+    /// Directory to write the SVGs rendered by --batch to.
+    #[cfg(feature = "serde")]
+    #[arg(long, value_name = "DIR", requires = "batch")]
+    out_dir: Option<PathBuf>,
+    /// Use one of the built-in examples instead of the arguments above (i.e. "Hilbert").
+    #[arg(
+        long,
+        value_name = "NAME",
+        value_parser = example,
+        group = "grammar",
+        conflicts_with_all = ["axiom", "rules"]
+    )]
+    example: Option<&'static Example>,
+    /// Number of times the rules of --example will run, instead of its own.
+    #[arg(long = "iterations", value_name = "ITERATIONS", requires = "example")]
+    example_iterations: Option<usize>,
+    /// Turn angle of --example in degrees, instead of its own.
+    #[arg(long = "angle", value_name = "ANGLE", requires = "example")]
+    example_angle: Option<Decimal>,
+
     /// Width of the SVG Canvas in millimeters.
     #[arg(long)]
     width: Decimal,
@@ -35,59 +91,347 @@ struct Args {
     /// Path to write the SVG to.
     #[arg(short, long, value_name = "FILE")]
     out: Option<PathBuf>,
+    /// Print how far rendering has gotten to stderr.
+    #[arg(long)]
+    progress: bool,
 }
 
 fn main() {
     let args = Args::parse();
-    let rules = args
-        .rules
+    #[cfg(feature = "serde")]
+    if let (Some(batch), Some(out_dir)) = (&args.batch, &args.out_dir) {
+        let results = std::fs::read_to_string(batch)
+            .map_err(|err| format!("Could not read {}: {err}", batch.display()))
+            .and_then(|grammars| render_batch(&grammars, out_dir, &svg_options(&args)));
+        match results {
+            Ok(results) => {
+                let mut failed = false;
+                for Rendered { name, path } in results {
+                    match path {
+                        Ok(path) => eprintln!("{name}: wrote {}", path.display()),
+                        Err(err) => {
+                            eprintln!("{name}: {err}");
+                            failed = true;
+                        }
+                    }
+                }
+                if failed {
+                    std::process::exit(1);
+                }
+            }
+            Err(err) => {
+                eprintln!("{err}");
+                std::process::exit(1);
+            }
+        }
+        return;
+    }
+
+    let l_system = match l_system(&args, std::io::stdin()) {
+        Ok(l_system) => l_system,
+        Err(err) => {
+            eprintln!("{err}");
+            std::process::exit(1);
+        }
+    };
+    for v in l_system
+        .variables_to_draw
         .iter()
-        .map(|r| {
-            let (c, replacement) = r.split_once("=>").expect("rule contains =>");
-            assert_eq!(c.chars().count(), 1, "=> is preceded by a single char");
-            (
-                c.chars().next().expect("character count is at least 1"),
-                replacement,
-            )
-        })
-        .collect::<HashMap<_, _>>();
-    let variables_to_draw: HashSet<char> = HashSet::from_iter(args.variables_to_draw.chars());
-    for v in variables_to_draw.iter().copied().chain(args.axiom.chars()) {
-        if !rules.contains_key(&v) && !matches!(v, '+' | '-' | '|' | '[' | ']') {
+        .copied()
+        .chain(l_system.axiom.chars())
+    {
+        if !l_system.rules.contains_key(&v) && !lsys::is_operator(v) {
             eprintln!(
                 r#"There is no replacement rule for `{v}`! Assuming self-replacement ("{v}=>{v}")"#
             )
         }
     }
 
+    let options = svg_options(&args);
     let mut writer = args
         .out
         .map(|o| {
             Box::new(File::create(o).expect("valid file path with permissions")) as Box<dyn Write>
         })
         .unwrap_or_else(|| Box::new(std::io::stdout()) as Box<dyn Write>);
-    LSystem {
-        axiom: args.axiom,
-        variables_to_draw,
-        // Degrees to radians
-        angle: args.angle / Decimal::from_usize(180).expect("180 is a decimal") * Decimal::PI,
-        iterations: args.iterations,
+    if args.progress {
+        l_system
+            .to_svg_with_progress(&options, &mut writer, |progress| match progress {
+                Progress::Expanding {
+                    iteration,
+                    iterations,
+                } => eprint!("\rExpanding iteration {iteration}/{iterations}"),
+                Progress::Drawing {
+                    walked,
+                    len,
+                    strokes,
+                } => eprint!("\rDrawing {walked}/{len} symbols, {strokes} strokes"),
+            })
+            .unwrap();
+        eprintln!();
+    } else {
+        l_system.to_svg(&options, &mut writer).unwrap();
+    }
+}
+
+fn svg_options(args: &Args) -> SvgOptions {
+    SvgOptions {
+        width: args.width,
+        height: args.height,
+        units: LengthUnit::Mm,
+        ..Default::default()
+    }
+}
+
+/// L-system described by the example, the grammar file, `stdin` or the positional arguments.
+fn l_system<I: Read>(args: &Args, stdin: I) -> Result<LSystem<String, String>, String> {
+    if let Some(example) = args.example {
+        let mut l_system = example
+            .l_system()
+            .map_err(|err| format!("Invalid example: {err}"))?;
+        if let Some(iterations) = args.example_iterations {
+            l_system.iterations = iterations;
+        }
+        if let Some(angle) = args.example_angle {
+            l_system = l_system.with_angle_degrees(angle);
+        }
+        return Ok(l_system);
+    }
+    #[cfg(feature = "serde")]
+    if let Some(path) = &args.json {
+        let file =
+            File::open(path).map_err(|err| format!("Could not read {}: {err}", path.display()))?;
+        let l_system: LSystem<String, String> =
+            serde_json::from_reader(std::io::BufReader::new(file))
+                .map_err(|err| format!("Invalid JSON grammar: {err}"))?;
+        validate(&l_system).map_err(|err| format!("Invalid JSON grammar: {err}"))?;
+        return Ok(l_system);
+    }
+    if args.stdin {
+        let grammar = std::io::read_to_string(stdin)
+            .map_err(|err| format!("Could not read standard input: {err}"))?;
+        let l_system = parse_compact_grammar(&grammar)?;
+        validate(&l_system).map_err(|err| format!("Invalid grammar: {err}"))?;
+        return Ok(l_system);
+    }
+    match &args.file {
+        Some(path) => std::fs::read_to_string(path)
+            .map_err(|err| format!("Could not read {}: {err}", path.display()))
+            .and_then(|grammar| parse_grammar(&grammar)),
+        None => l_system_from_args(args),
+    }
+}
+
+/// All problems found by [`LSystem::validate`], as one message.
+fn validate(l_system: &LSystem<String, String>) -> Result<(), String> {
+    l_system.validate().map_err(|errors| {
+        errors
+            .iter()
+            .map(ToString::to_string)
+            .collect::<Vec<_>>()
+            .join(", ")
+    })
+}
+
+/// L-system with a name, as read by --batch.
+#[cfg(feature = "serde")]
+#[derive(serde::Deserialize)]
+struct NamedLSystem {
+    name: String,
+    #[serde(flatten)]
+    l_system: LSystem<String, String>,
+}
+
+/// Outcome of rendering one L-system with --batch.
+#[cfg(feature = "serde")]
+struct Rendered {
+    name: String,
+    /// Path of the SVG, or the problem that stopped it from being written.
+    path: Result<PathBuf, String>,
+}
+
+/// Render each L-system in a JSON array of [`NamedLSystem`]s to an SVG in `out_dir`, named after
+/// it.
+///
+/// One bad L-system does not stop the rest from being rendered.
+#[cfg(feature = "serde")]
+fn render_batch(
+    grammars: &str,
+    out_dir: &std::path::Path,
+    options: &SvgOptions,
+) -> Result<Vec<Rendered>, String> {
+    let grammars: Vec<NamedLSystem> =
+        serde_json::from_str(grammars).map_err(|err| format!("Invalid batch: {err}"))?;
+    std::fs::create_dir_all(out_dir)
+        .map_err(|err| format!("Could not create {}: {err}", out_dir.display()))?;
+    Ok(grammars
+        .into_iter()
+        .map(|NamedLSystem { name, l_system }| {
+            let path = out_dir.join(format!("{}.svg", slugify(&name)));
+            let path = validate(&l_system).and_then(|()| {
+                let file = File::create(&path)
+                    .map_err(|err| format!("Could not create {}: {err}", path.display()))?;
+                l_system
+                    .to_svg(options, std::io::BufWriter::new(file))
+                    .map_err(|err| err.to_string())?;
+                Ok(path)
+            });
+            Rendered { name, path }
+        })
+        .collect())
+}
+
+/// Lowercase `name` with runs of anything but letters and digits replaced by `-`.
+#[cfg(feature = "serde")]
+fn slugify(name: &str) -> String {
+    name.split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(str::to_lowercase)
+        .collect::<Vec<_>>()
+        .join("-")
+}
+
+/// Built-in example with the given name, ignoring case.
+fn example(name: &str) -> Result<&'static Example, String> {
+    lsys::examples()
+        .iter()
+        .find(|example| example.name.eq_ignore_ascii_case(name))
+        .ok_or_else(|| {
+            let names = lsys::examples()
+                .iter()
+                .map(|example| example.name)
+                .collect::<Vec<_>>();
+            format!("expected one of: {}", names.join(", "))
+        })
+}
+
+/// L-system described by the positional arguments.
+fn l_system_from_args(args: &Args) -> Result<LSystem<String, String>, String> {
+    let rules =
+        lsys::parse_rules(&args.rules.join("\n")).map_err(|err| format!("Invalid rules: {err}"))?;
+    let required = "required without a grammar file";
+    Ok(LSystem {
+        axiom: args.axiom.clone().expect(required),
+        variables_to_draw: HashSet::from_iter(
+            args.variables_to_draw.as_ref().expect(required).chars(),
+        ),
+        iterations: args.iterations.expect(required),
+        rules,
+        ..Default::default()
+    }
+    .with_angle_degrees(args.angle.expect(required)))
+}
+
+/// Parse an L-system from the format read with `--file`:
+///
+/// ```text
+/// F
+/// draw: F
+/// angle: 90
+/// iterations: 4
+/// F=>F+F-F-F+F
+/// ```
+fn parse_grammar(grammar: &str) -> Result<LSystem<String, String>, String> {
+    let mut lines = grammar.lines().filter(|line| !line.trim().is_empty());
+    let axiom = lines.next().ok_or("The file is empty")?.trim().to_string();
+    let mut variables_to_draw = None;
+    let mut angle = None;
+    let mut iterations = None;
+    let mut rules = vec![];
+    for line in lines {
+        match line.split_once(':') {
+            Some(("draw", value)) => variables_to_draw = Some(value.trim().chars().collect()),
+            Some(("angle", value)) => {
+                angle = Some(
+                    value
+                        .trim()
+                        .parse::<Decimal>()
+                        .map_err(|err| format!("Invalid angle: {err}"))?,
+                )
+            }
+            Some(("iterations", value)) => {
+                iterations = Some(
+                    value
+                        .trim()
+                        .parse::<usize>()
+                        .map_err(|err| format!("Invalid iterations: {err}"))?,
+                )
+            }
+            _ => rules.push(line),
+        }
+    }
+    let rules =
+        lsys::parse_rules(&rules.join("\n")).map_err(|err| format!("Invalid rules: {err}"))?;
+
+    Ok(LSystem {
+        axiom,
+        variables_to_draw: variables_to_draw.ok_or("Missing draw: header")?,
+        iterations: iterations.ok_or("Missing iterations: header")?,
+        rules,
+        ..Default::default()
+    }
+    .with_angle_degrees(angle.ok_or("Missing angle: header")?))
+}
+
+/// Parse a one line grammar of the form `axiom|variables|rules|angle|iterations`, with rules
+/// separated by commas. The turn around operator `|` cannot be used, since it separates fields.
+fn parse_compact_grammar(grammar: &str) -> Result<LSystem<String, String>, String> {
+    let fields = grammar.trim().split('|').map(str::trim).collect::<Vec<_>>();
+    let [axiom, variables_to_draw, rules, angle, iterations] = fields[..] else {
+        if fields.len() > 5 {
+            return Err(format!(
+                "Expected 5 fields separated by | (axiom|variables|rules|angle|iterations), found \
+                 {}; the turn around operator | cannot be used in a one line grammar",
+                fields.len()
+            ));
+        }
+        return Err(format!(
+            "Expected 5 fields separated by | (axiom|variables|rules|angle|iterations), found {}",
+            fields.len()
+        ));
+    };
+    let rules = lsys::parse_rules(&rules.split(',').collect::<Vec<_>>().join("\n"))
+        .map_err(|err| format!("Invalid rules: {err}"))?;
+
+    Ok(LSystem {
+        axiom: axiom.to_string(),
+        variables_to_draw: variables_to_draw.chars().collect(),
+        iterations: iterations
+            .parse()
+            .map_err(|err| format!("Invalid iterations: {err}"))?,
         rules,
+        ..Default::default()
     }
-    .to_svg(
-        &SvgOptions {
-            width: args.width,
-            height: args.height,
-            units: LengthUnit::Mm,
-        },
-        &mut writer,
-    )
-    .unwrap();
+    .with_angle_degrees(
+        angle
+            .parse::<Decimal>()
+            .map_err(|err| format!("Invalid angle: {err}"))?,
+    ))
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use lsys::parametric::*;
+    use lsys::tokenized::TokenizedLSystem;
+    use lsys::CompactGrammarError;
+    use lsys::ContextRule;
+    use lsys::FillRule;
+    use lsys::LSystemError;
+    use lsys::LineCap;
+    use lsys::LineJoin;
+    use lsys::PaletteError;
+    use lsys::ParseError;
+    use lsys::Placement;
+    use lsys::PlotterOptions;
+    use lsys::RenderError;
+    use lsys::ShapeRendering;
+    use lsys::Stroke;
+    use lsys::Theme;
+    use rust_decimal::prelude::FromPrimitive;
+    use rust_decimal::MathematicalOps;
+    use std::collections::HashMap;
+    use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 
     fn harness(
         axiom: &str,
@@ -103,29 +447,3355 @@ mod tests {
             variables_to_draw: HashSet::from_iter(variables_to_draw.iter().copied()),
             angle,
             iterations,
-            rules: rules
+            rules: lsys::parse_rules(&rules.join("\n")).unwrap(),
+            ..Default::default()
+        }
+        .to_svg(
+            &SvgOptions {
+                width: Decimal::ONE_HUNDRED,
+                height: Decimal::ONE_HUNDRED,
+                units: LengthUnit::Mm,
+                ..Default::default()
+            },
+            &mut actual,
+        )
+        .unwrap();
+
+        assert_eq!(
+            expected,
+            String::from_utf8(actual).expect("SVG is valid UTF-8")
+        );
+    }
+
+    /// Points in the `d` attribute of the first path in `svg`.
+    fn path_points(svg: &str) -> Vec<(Decimal, Decimal)> {
+        let path = svg.split(" d=\"").nth(1).unwrap();
+        let path = &path[..path.find('"').unwrap()];
+        let coordinates = path
+            .split_whitespace()
+            .filter(|token| !matches!(*token, "M" | "L"))
+            .map(|token| Decimal::from_str_exact(token).unwrap())
+            .collect::<Vec<_>>();
+        coordinates
+            .chunks(2)
+            .map(|point| (point[0], point[1]))
+            .collect()
+    }
+
+    #[test]
+    fn length_unit_suffix() {
+        for (unit, suffix) in [
+            (LengthUnit::None, ""),
+            (LengthUnit::Em, "em"),
+            (LengthUnit::Ex, "ex"),
+            (LengthUnit::Px, "px"),
+            (LengthUnit::In, "in"),
+            (LengthUnit::Cm, "cm"),
+            (LengthUnit::Mm, "mm"),
+            (LengthUnit::Pt, "pt"),
+            (LengthUnit::Pc, "pc"),
+            (LengthUnit::Percent, "%"),
+        ] {
+            assert_eq!(lsys::length_unit_suffix(unit), suffix);
+        }
+
+        let mut actual = vec![];
+        LSystem::<_, &str> {
+            axiom: "F+F",
+            variables_to_draw: HashSet::from_iter(['F']),
+            angle: Decimal::HALF_PI,
+            ..Default::default()
+        }
+        .to_svg(
+            &SvgOptions {
+                units: LengthUnit::Percent,
+                ..Default::default()
+            },
+            &mut actual,
+        )
+        .unwrap();
+        let actual = String::from_utf8(actual).unwrap();
+        assert!(actual.contains(r#"width="100%" height="100%" viewBox="0 0 100 100""#));
+    }
+
+    #[test]
+    fn examples() {
+        assert!(lsys::examples()
+            .iter()
+            .any(|example| example.name == "Hilbert"));
+        for example in lsys::examples() {
+            let l_system = example.l_system().unwrap();
+            assert_eq!(l_system.validate(), Ok(()), "{}", example.name);
+            l_system
+                .to_svg(&SvgOptions::default(), std::io::sink())
+                .unwrap();
+        }
+    }
+
+    #[test]
+    fn grammar_example() {
+        let args = Args::parse_from(["lsys", "--example", "Koch", "--width=100", "--height=100"]);
+        let mut actual = vec![];
+        l_system(&args, std::io::empty())
+            .unwrap()
+            .to_svg(&svg_options(&args), &mut actual)
+            .unwrap();
+        assert_eq!(
+            include_str!("../tests/koch.svg"),
+            String::from_utf8(actual).unwrap()
+        );
+
+        let args = Args::parse_from([
+            "lsys",
+            "--example",
+            "hilbert",
+            "--iterations",
+            "7",
+            "--angle",
+            "60",
+            "--width=100",
+            "--height=100",
+        ]);
+        let overridden = l_system(&args, std::io::empty()).unwrap();
+        let mut expected = lsys::examples()
+            .iter()
+            .find(|example| example.name == "Hilbert")
+            .unwrap()
+            .l_system()
+            .unwrap()
+            .with_angle_degrees(60);
+        expected.iterations = 7;
+        assert_eq!(overridden, expected);
+
+        let Err(unknown) = Args::try_parse_from([
+            "lsys",
+            "--example",
+            "Unknown",
+            "--width=100",
+            "--height=100",
+        ]) else {
+            panic!("unknown examples are rejected");
+        };
+        let unknown = unknown.to_string();
+        assert!(unknown.contains("Koch, Sierpinski Triangle"), "{unknown}");
+    }
+
+    #[test]
+    fn to_grammar_string() {
+        let l_system = LSystem {
+            axiom: "F-G-G".to_string(),
+            variables_to_draw: HashSet::from_iter(['G', 'F']),
+            iterations: 3,
+            rules: HashMap::from_iter([('G', "GG".to_string()), ('F', "F-G+F+G-F".to_string())]),
+            ..Default::default()
+        }
+        .with_angle_degrees(120);
+        assert_eq!(
+            l_system.to_grammar_string(),
+            "F-G-G\ndraw: FG\nangle: 120\niterations: 3\nF=>F-G+F+G-F\nG=>GG\n"
+        );
+        assert_eq!(parse_grammar(&l_system.to_grammar_string()), Ok(l_system));
+
+        for example in lsys::examples() {
+            let l_system = example.l_system().unwrap();
+            assert_eq!(parse_grammar(&l_system.to_grammar_string()), Ok(l_system));
+        }
+    }
+
+    #[test]
+    fn grammar_stdin() {
+        let args = Args::parse_from(["lsys", "--stdin", "--width=100", "--height=100"]);
+        assert!(args.stdin);
+
+        let mut actual = vec![];
+        l_system(&args, "F|F|F=>F+F-F-F+F|90|4\n".as_bytes())
+            .unwrap()
+            .to_svg(&svg_options(&args), &mut actual)
+            .unwrap();
+        assert_eq!(
+            include_str!("../tests/koch.svg"),
+            String::from_utf8(actual).unwrap()
+        );
+
+        assert_eq!(
+            parse_compact_grammar(" F-G-G | FG | F=>F-G+F+G-F, G=>GG | 120 | 3 "),
+            parse_grammar("F-G-G\ndraw: FG\nangle: 120\niterations: 3\nF=>F-G+F+G-F\nG=>GG")
+        );
+        assert!(parse_compact_grammar("F|F|F=>F+F|90").is_err());
+        assert!(parse_compact_grammar("F|F|F+F|90|4").is_err());
+        // | separates fields, so it cannot turn around
+        assert!(parse_compact_grammar("F|F|F=>F|F|180|1")
+            .unwrap_err()
+            .contains("turn around operator"));
+        assert!(l_system(&args, "F|F|F=>F+F|90".as_bytes())
+            .unwrap_err()
+            .starts_with("Expected 5 fields"));
+    }
+
+    #[test]
+    fn grammar_file() {
+        let grammar = "\
+F-G-G
+draw: FG
+angle: 120
+iterations: 3
+F=>F-G+F+G-F
+G=>GG
+";
+        let path = std::env::temp_dir().join(format!("lsys-{}.lsys", std::process::id()));
+        std::fs::write(&path, grammar).unwrap();
+        let from_file = Args::parse_from([
+            "lsys".as_ref(),
+            "--file".as_ref(),
+            path.as_os_str(),
+            "--width=100".as_ref(),
+            "--height=100".as_ref(),
+        ]);
+        let from_args = Args::parse_from([
+            "lsys",
+            "F-G-G",
+            "FG",
+            "120",
+            "3",
+            "F=>F-G+F+G-F",
+            "G=>GG",
+            "--width=100",
+            "--height=100",
+        ]);
+
+        let from_file = l_system(&from_file, std::io::empty());
+        std::fs::remove_file(&path).unwrap();
+        let from_args = l_system(&from_args, std::io::empty());
+        assert_eq!(from_file, from_args);
+
+        let mut from_file_svg = vec![];
+        from_file
+            .unwrap()
+            .to_svg(&SvgOptions::default(), &mut from_file_svg)
+            .unwrap();
+        let mut from_args_svg = vec![];
+        from_args
+            .unwrap()
+            .to_svg(&SvgOptions::default(), &mut from_args_svg)
+            .unwrap();
+        assert_eq!(from_file_svg, from_args_svg);
+
+        assert_eq!(
+            parse_grammar("F\ndraw: F\nangle: 90\n"),
+            Err("Missing iterations: header".to_string())
+        );
+        assert!(Args::try_parse_from(["lsys", "--width=1", "--height=1"]).is_err());
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn grammar_json() {
+        let l_system = LSystem {
+            axiom: "F".to_string(),
+            variables_to_draw: HashSet::from_iter(['F']),
+            angle: Decimal::HALF_PI,
+            iterations: 2,
+            rules: HashMap::from_iter([('F', "F+F-F-F+F".to_string())]),
+            ..Default::default()
+        };
+        let path = std::env::temp_dir().join(format!("lsys-{}.json", std::process::id()));
+        std::fs::write(&path, serde_json::to_string(&l_system).unwrap()).unwrap();
+        let args = Args::parse_from([
+            "lsys".as_ref(),
+            "--json".as_ref(),
+            path.as_os_str(),
+            "--width=100".as_ref(),
+            "--height=100".as_ref(),
+        ]);
+        let from_json = super::l_system(&args, std::io::empty());
+
+        std::fs::write(&path, r#"{"axiom": "F]", "variables_to_draw": ["F"], "angle": "0", "iterations": 0, "rules": {}}"#).unwrap();
+        let invalid = super::l_system(&args, std::io::empty());
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(from_json.as_ref(), Ok(&l_system));
+        let mut expected = vec![];
+        l_system
+            .to_svg(&SvgOptions::default(), &mut expected)
+            .unwrap();
+        let mut actual = vec![];
+        from_json
+            .unwrap()
+            .to_svg(&SvgOptions::default(), &mut actual)
+            .unwrap();
+        assert_eq!(actual, expected);
+
+        assert_eq!(
+            invalid,
+            Err("Invalid JSON grammar: 1 `]` without a matching `[`".to_string())
+        );
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn render_batch() {
+        let out_dir = std::env::temp_dir().join(format!("lsys-batch-{}", std::process::id()));
+        let results = super::render_batch(
+            r#"[
+                {"name": "Koch Curve", "axiom": "F", "variables_to_draw": ["F"], "angle": "1.5707963267948966192313216916", "iterations": 2, "rules": {"F": "F+F-F-F+F"}},
+                {"name": "Sierpinski Triangle!", "axiom": "F-G-G", "variables_to_draw": ["F", "G"], "angle": "2.0943951023931954923084289221", "iterations": 2, "rules": {"F": "F-G+F+G-F", "G": "GG"}},
+                {"name": "Broken", "axiom": "F]", "variables_to_draw": ["F"], "angle": "0", "iterations": 0, "rules": {}}
+            ]"#,
+            &out_dir,
+            &SvgOptions::default(),
+        )
+        .unwrap();
+
+        let mut files = std::fs::read_dir(&out_dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+            .collect::<Vec<_>>();
+        files.sort();
+        std::fs::remove_dir_all(&out_dir).unwrap();
+
+        assert_eq!(files, ["koch-curve.svg", "sierpinski-triangle.svg"]);
+        assert_eq!(
+            results
                 .iter()
-                .map(|r| {
-                    let (c, r) = r.split_once("=>").unwrap();
+                .map(|rendered| (rendered.name.as_str(), rendered.path.is_ok()))
+                .collect::<Vec<_>>(),
+            [
+                ("Koch Curve", true),
+                ("Sierpinski Triangle!", true),
+                ("Broken", false)
+            ]
+        );
+    }
 
-                    (c.chars().next().unwrap(), r)
-                })
-                .collect(),
+    #[test]
+    fn step() {
+        let l_system = LSystem {
+            axiom: "A",
+            variables_to_draw: HashSet::from_iter(['F']),
+            angle: Decimal::HALF_PI,
+            iterations: 5,
+            rules: HashMap::from_iter([('A', "B-F+A"), ('B', "A+F-B")]),
+            context_rules: vec![ContextRule {
+                left: Some('F'),
+                variable: 'A',
+                right: None,
+                replacement: "AB",
+            }],
+            ..Default::default()
+        };
+        let mut state = l_system.axiom.to_string();
+        for _ in 0..l_system.iterations {
+            state = l_system.step(&state);
+        }
+        assert_eq!(state, l_system.calculate_final_state());
+
+        // Any state can be stepped, not only those reached from the axiom
+        assert_eq!(l_system.step("BXA"), "A+F-BXB-F+A");
+    }
+
+    #[test]
+    #[cfg(feature = "parallel")]
+    fn parallel_expansion() {
+        for example in lsys::examples() {
+            let mut l_system = example.l_system().unwrap();
+            // Some examples stop growing, so they stay serial
+            while l_system.iterations < 20 && l_system.calculate_final_state().len() < 1 << 16 {
+                l_system.iterations += 1;
+            }
+            // Symbols are always produced serially
+            assert_eq!(
+                l_system.calculate_final_state(),
+                l_system.symbols().collect::<String>(),
+                "{}",
+                example.name
+            );
+        }
+
+        let l_system = LSystem {
+            axiom: format!("X{}Y", "F".repeat(1 << 16)),
+            variables_to_draw: HashSet::from_iter(['F']),
+            iterations: 1,
+            rules: HashMap::from_iter([('F', "F+F")]),
+            ..Default::default()
+        };
+        assert_eq!(
+            l_system.try_calculate_final_state(),
+            Err(LSystemError::MissingRule('X'))
+        );
+    }
+
+    #[test]
+    fn prepared() {
+        let l_system = LSystem {
+            axiom: "F".to_string(),
+            variables_to_draw: HashSet::from_iter(['F']),
+            angle: Decimal::HALF_PI,
+            iterations: 3,
+            rules: HashMap::from_iter([('F', "F+F-F-F+F".to_string())]),
+            ..Default::default()
+        };
+        let options = SvgOptions::default();
+        let mut prepared = l_system.clone().prepared();
+        assert_eq!(
+            prepared.to_svg_string(&options).unwrap(),
+            l_system.to_svg_string(&options).unwrap()
+        );
+
+        // Redrawing with a new angle reuses the final state
+        let final_state = prepared.final_state().unwrap().as_ptr();
+        prepared.l_system.angle = Decimal::QUARTER_PI;
+        assert_eq!(
+            prepared.to_svg_string(&options).unwrap(),
+            LSystem {
+                angle: Decimal::QUARTER_PI,
+                ..l_system.clone()
+            }
+            .to_svg_string(&options)
+            .unwrap()
+        );
+        assert_eq!(prepared.final_state().unwrap().as_ptr(), final_state);
+
+        // Lowering the maximum state length checks the final state again
+        prepared.l_system.max_state_len = Some(10);
+        assert!(matches!(
+            prepared.to_svg_string(&options),
+            Err(RenderError::LSystem(LSystemError::StateTooLarge { .. }))
+        ));
+        prepared.l_system.iterations = 1;
+        assert_eq!(prepared.final_state().unwrap(), "F+F-F-F+F");
+    }
+
+    #[test]
+    fn missing_rule_is_identity() {
+        let final_state = LSystem {
+            axiom: "FX",
+            variables_to_draw: HashSet::from_iter(['F']),
+            angle: Decimal::HALF_PI,
+            iterations: 1,
+            rules: HashMap::from_iter([('F', "F+F")]),
+            ..Default::default()
+        }
+        .calculate_final_state();
+        assert_eq!(final_state, "F+FX");
+    }
+
+    #[test]
+    fn strict_missing_rule() {
+        let l_system = LSystem {
+            axiom: "FX",
+            variables_to_draw: HashSet::from_iter(['F']),
+            angle: Decimal::HALF_PI,
+            iterations: 2,
+            rules: HashMap::from_iter([('F', "F+F")]),
+            ..Default::default()
+        };
+        assert_eq!(
+            l_system.try_calculate_final_state(),
+            Err(LSystemError::MissingRule('X'))
+        );
+        assert_eq!(l_system.calculate_final_state(), "F+F+F+FX");
+
+        // The final state is checked too, whatever the number of iterations
+        for (axiom, rules, iterations) in [("FX", "", 0), ("F", "F=>FX", 1), ("F", "F=>FX", 2)] {
+            let l_system = LSystem {
+                axiom: axiom.to_string(),
+                variables_to_draw: HashSet::from_iter(['F']),
+                iterations,
+                rules: lsys::parse_rules(rules).unwrap(),
+                ..Default::default()
+            };
+            assert_eq!(
+                l_system.try_calculate_final_state(),
+                Err(LSystemError::MissingRule('X'))
+            );
+            assert_eq!(
+                l_system.validate(),
+                Err(vec![LSystemError::MissingRule('X')])
+            );
+        }
+
+        // Letters the turtle interprets need no rule, as in validate
+        let l_system = LSystem {
+            axiom: "FXY",
+            variables_to_move: HashSet::from_iter(['X']),
+            turn_angles: HashMap::from_iter([('Y', Decimal::HALF_PI)]),
+            ..l_system
+        };
+        assert_eq!(
+            l_system.try_calculate_final_state(),
+            Ok("F+F+F+FXY".to_string())
+        );
+        assert_eq!(l_system.validate(), Ok(()));
+    }
+
+    #[test]
+    fn no_strokes() {
+        let mut actual = vec![];
+        LSystem {
+            axiom: "+-+-",
+            variables_to_draw: HashSet::new(),
+            angle: Decimal::HALF_PI,
+            iterations: 1,
+            rules: HashMap::<char, &str>::new(),
+            ..Default::default()
+        }
+        .to_svg(
+            &SvgOptions {
+                width: Decimal::ONE_HUNDRED,
+                height: Decimal::ONE_HUNDRED,
+                units: LengthUnit::Mm,
+                ..Default::default()
+            },
+            &mut actual,
+        )
+        .unwrap();
+
+        let actual = String::from_utf8(actual).unwrap();
+        assert!(actual.contains(r#"width="100mm" height="100mm""#));
+        assert!(!actual.contains("<path"));
+        assert!(actual.ends_with("</svg>\n"));
+    }
+
+    #[test]
+    fn straight_line() {
+        let mut actual = vec![];
+        LSystem {
+            axiom: "FFF",
+            variables_to_draw: HashSet::from_iter(['F']),
+            angle: Decimal::ZERO,
+            iterations: 0,
+            rules: HashMap::<char, &str>::new(),
+            ..Default::default()
+        }
+        .to_svg(
+            &SvgOptions {
+                width: Decimal::ONE_HUNDRED,
+                height: Decimal::ONE_HUNDRED,
+                units: LengthUnit::Mm,
+                ..Default::default()
+            },
+            &mut actual,
+        )
+        .unwrap();
+
+        let actual = String::from_utf8(actual).unwrap();
+        let (_, path) = actual.split_once(" d=\"").unwrap();
+        let (path, _) = path.split_once('"').unwrap();
+        for coordinate in path.split(' ').filter(|s| !matches!(*s, "M" | "L")) {
+            let coordinate: Decimal = coordinate.parse().unwrap();
+            assert!((Decimal::ZERO..=Decimal::ONE).contains(&coordinate));
+        }
+    }
+
+    #[test]
+    fn unbalanced_brackets() {
+        let mut actual = vec![];
+        LSystem {
+            axiom: "F]F",
+            variables_to_draw: HashSet::from_iter(['F']),
+            angle: Decimal::HALF_PI,
+            iterations: 1,
+            rules: HashMap::<char, &str>::new(),
+            ..Default::default()
         }
         .to_svg(
             &SvgOptions {
                 width: Decimal::ONE_HUNDRED,
                 height: Decimal::ONE_HUNDRED,
                 units: LengthUnit::Mm,
+                ..Default::default()
             },
             &mut actual,
         )
         .unwrap();
 
+        assert!(String::from_utf8(actual).unwrap().contains("<path"));
+    }
+
+    #[test]
+    fn validate() {
+        let l_system = |axiom| LSystem::<_, &str> {
+            axiom,
+            variables_to_draw: HashSet::from_iter(['F']),
+            angle: Decimal::HALF_PI,
+            ..Default::default()
+        };
+        assert_eq!(l_system("F[+F][-F]F").validate(), Ok(()));
         assert_eq!(
-            expected,
-            String::from_utf8(actual).expect("cairo writes valid utf8")
+            l_system("F[+F]]-F]").validate(),
+            Err(vec![LSystemError::UnmatchedClose(2)])
+        );
+        assert_eq!(
+            l_system("F[+F[-F").validate(),
+            Err(vec![LSystemError::UnclosedBranch(2)])
+        );
+        assert_eq!(
+            l_system("[X]+Y]X").validate(),
+            Err(vec![
+                LSystemError::MissingRule('X'),
+                LSystemError::MissingRule('Y'),
+                LSystemError::UnmatchedClose(1),
+                LSystemError::NothingToDraw,
+            ])
+        );
+    }
+
+    #[test]
+    fn from_str() {
+        let l_system = "F; F; 90; 4; F=>F+F-F-F+F, G => GG"
+            .parse::<LSystem<String, String>>()
+            .unwrap();
+        assert_eq!(l_system.axiom, "F");
+        assert_eq!(l_system.variables_to_draw, HashSet::from_iter(['F']));
+        assert_eq!(l_system.angle, Decimal::HALF_PI);
+        assert_eq!(l_system.iterations, 4);
+        assert_eq!(
+            l_system.rules,
+            HashMap::from_iter([('F', "F+F-F-F+F".to_string()), ('G', "GG".to_string())])
+        );
+
+        let parse = |s: &str| s.parse::<LSystem<String, String>>().unwrap_err();
+        assert_eq!(parse("F;F;90;4"), CompactGrammarError::FieldCount(4));
+        assert_eq!(parse(" ;F;90;4;F=>FF"), CompactGrammarError::EmptyAxiom);
+        assert_eq!(
+            parse("F;F;ninety;4;F=>FF"),
+            CompactGrammarError::InvalidAngle("ninety".to_string())
+        );
+        assert_eq!(
+            parse("F;F;90;-1;F=>FF"),
+            CompactGrammarError::InvalidIterations("-1".to_string())
+        );
+        assert_eq!(
+            parse("F;F;90;4;F=>FF,GG"),
+            CompactGrammarError::InvalidRules(ParseError::MissingArrow { line: 2 })
+        );
+    }
+
+    #[test]
+    fn validate_angle() {
+        let l_system = |angle| LSystem::<_, &str> {
+            axiom: "F+F-F",
+            variables_to_draw: HashSet::from_iter(['F']),
+            angle,
+            ..Default::default()
+        };
+        assert_eq!(l_system(Decimal::TWO_PI).validate(), Ok(()));
+        assert_eq!(
+            l_system(Decimal::ZERO).validate(),
+            Err(vec![LSystemError::ZeroAngle])
+        );
+        // Meant as degrees
+        assert_eq!(
+            l_system(Decimal::from(90)).validate(),
+            Err(vec![LSystemError::AngleOutOfRange(Decimal::from(90))])
+        );
+        assert_eq!(
+            l_system(Decimal::from(-90)).validate(),
+            Err(vec![LSystemError::AngleOutOfRange(Decimal::from(-90))])
+        );
+
+        // Nothing turns
+        let straight = LSystem::<_, &str> {
+            axiom: "FF",
+            ..l_system(Decimal::ZERO)
+        };
+        assert_eq!(straight.validate(), Ok(()));
+    }
+
+    #[test]
+    fn with_angle_degrees() {
+        let l_system = LSystem {
+            axiom: "F",
+            variables_to_draw: HashSet::from_iter(['F']),
+            iterations: 3,
+            rules: HashMap::from_iter([('F', "F+F-F-F+F")]),
+            ..Default::default()
+        };
+        let mut degrees = vec![];
+        l_system
+            .clone()
+            .with_angle_degrees(90)
+            .to_svg(&SvgOptions::default(), &mut degrees)
+            .unwrap();
+        let mut radians = vec![];
+        LSystem {
+            angle: Decimal::HALF_PI,
+            ..l_system
+        }
+        .to_svg(&SvgOptions::default(), &mut radians)
+        .unwrap();
+        assert_eq!(degrees, radians);
+    }
+
+    #[test]
+    fn gcode() {
+        let mut actual = vec![];
+        LSystem::<_, &str> {
+            axiom: "F+FfF",
+            variables_to_draw: HashSet::from_iter(['F']),
+            variables_to_move: HashSet::from_iter(['f']),
+            angle: Decimal::HALF_PI,
+            initial_heading: Decimal::ZERO,
+            ..Default::default()
+        }
+        .to_gcode(&PlotterOptions::default(), &mut actual)
+        .unwrap();
+
+        let actual = String::from_utf8(actual).unwrap();
+        let lines = actual.lines().collect::<Vec<_>>();
+        assert_eq!(
+            lines,
+            [
+                "G21 ; millimeters",
+                "G90 ; absolute positioning",
+                "G0 Z1",
+                "G0 X0 Y100",
+                "G1 Z0",
+                "G1 X33.333 Y100",
+                "G1 X33.333 Y66.667",
+                "G0 Z1",
+                // Pen up to travel over the move
+                "G0 X33.333 Y33.333",
+                "G1 Z0",
+                "G1 X33.333 Y0",
+                "G0 Z1",
+                "G0 X0 Y0",
+            ]
+        );
+    }
+
+    #[test]
+    fn hpgl() {
+        let mut actual = vec![];
+        LSystem::<_, &str> {
+            axiom: "F+FfF",
+            variables_to_draw: HashSet::from_iter(['F']),
+            variables_to_move: HashSet::from_iter(['f']),
+            angle: Decimal::HALF_PI,
+            initial_heading: Decimal::ZERO,
+            ..Default::default()
+        }
+        .to_hpgl(&PlotterOptions::default(), &mut actual)
+        .unwrap();
+
+        let actual = String::from_utf8(actual).unwrap();
+        assert!(actual.starts_with("IN;"));
+        assert_eq!(actual.matches("PD;").count(), 2);
+        assert_eq!(actual.matches("PU;").count(), 3);
+        assert!(actual.contains("PU;\nPA0,4000;\nPD;\nPA1333,4000;\nPA1333,2667;\n"));
+    }
+
+    #[test]
+    fn builder() {
+        let l_system = LSystem::builder()
+            .axiom("F")
+            .rule('F', "F+F-F-F+F")
+            .draw('F')
+            .angle_degrees(90)
+            .iterations(4)
+            .build()
+            .unwrap();
+        assert_eq!(l_system.axiom, "F");
+        assert_eq!(l_system.variables_to_draw, HashSet::from_iter(['F']));
+        assert_eq!(l_system.angle, Decimal::HALF_PI);
+        assert_eq!(l_system.iterations, 4);
+        assert_eq!(
+            l_system.rules,
+            HashMap::from_iter([('F', "F+F-F-F+F".to_string())])
+        );
+
+        assert_eq!(
+            LSystem::builder()
+                .axiom("FX")
+                .rule('F', "F+F")
+                .draw('F')
+                .build()
+                .unwrap_err(),
+            LSystemError::MissingRule('X')
+        );
+    }
+
+    #[test]
+    fn variable_colors() {
+        let mut actual = vec![];
+        LSystem {
+            axiom: "X",
+            variables_to_draw: HashSet::from_iter(['F', 'G']),
+            angle: Decimal::PI * Decimal::from_u32(25).unwrap() / Decimal::from_u32(180).unwrap(),
+            iterations: 3,
+            rules: HashMap::from_iter([('X', "G-[[X]+X]+F[+FX]-X"), ('F', "FF"), ('G', "GG")]),
+            ..Default::default()
+        }
+        .to_svg(
+            &SvgOptions {
+                variable_colors: HashMap::from_iter([
+                    ('F', "green".to_string()),
+                    ('G', "brown".to_string()),
+                ]),
+                ..Default::default()
+            },
+            &mut actual,
+        )
+        .unwrap();
+
+        let actual = String::from_utf8(actual).unwrap();
+        assert_eq!(actual.matches("<path").count(), 2);
+        assert!(actual.contains(r#"stroke="green""#));
+        assert!(actual.contains(r#"stroke="brown""#));
+    }
+
+    #[test]
+    fn stroke_width() {
+        let l_system = LSystem::<_, &str> {
+            axiom: "F",
+            variables_to_draw: HashSet::from_iter(['F']),
+            ..Default::default()
+        };
+        let mut actual = vec![];
+        l_system
+            .to_svg(
+                &SvgOptions {
+                    stroke_width: Some(Decimal::new(25, 3)),
+                    ..Default::default()
+                },
+                &mut actual,
+            )
+            .unwrap();
+        assert!(String::from_utf8(actual)
+            .unwrap()
+            .contains(r#"stroke-width="0.025""#));
+
+        let mut actual = vec![];
+        l_system
+            .to_svg(&SvgOptions::default(), &mut actual)
+            .unwrap();
+        assert!(String::from_utf8(actual)
+            .unwrap()
+            .contains(r#"stroke-width="0.01""#));
+    }
+
+    #[test]
+    fn line_cap_and_join() {
+        let l_system = LSystem::<_, &str> {
+            axiom: "F+F",
+            variables_to_draw: HashSet::from_iter(['F']),
+            angle: Decimal::HALF_PI,
+            ..Default::default()
+        };
+        let mut actual = vec![];
+        l_system
+            .to_svg(
+                &SvgOptions {
+                    line_cap: LineCap::Round,
+                    line_join: LineJoin::Round,
+                    ..Default::default()
+                },
+                &mut actual,
+            )
+            .unwrap();
+        let actual = String::from_utf8(actual).unwrap();
+        assert!(actual.contains(r#"stroke-linecap="round" stroke-linejoin="round""#));
+
+        let mut actual = vec![];
+        l_system
+            .to_svg(&SvgOptions::default(), &mut actual)
+            .unwrap();
+        let actual = String::from_utf8(actual).unwrap();
+        assert!(actual.contains(r#"stroke-linecap="butt" stroke-linejoin="miter""#));
+    }
+
+    #[test]
+    fn to_svg_string() {
+        let l_system = LSystem {
+            axiom: "F",
+            variables_to_draw: HashSet::from_iter(['F']),
+            angle: Decimal::HALF_PI,
+            iterations: 3,
+            rules: HashMap::from_iter([('F', "F+F-F-F+F")]),
+            ..Default::default()
+        };
+        let options = SvgOptions {
+            palette: vec!["green".to_string(), "brown".to_string()],
+            ..Default::default()
+        };
+        let mut expected = vec![];
+        l_system.to_svg(&options, &mut expected).unwrap();
+        assert_eq!(
+            l_system.to_svg_string(&options).unwrap().as_bytes(),
+            expected
+        );
+    }
+
+    #[test]
+    fn adaptive_stroke_color() {
+        let l_system = LSystem::<_, &str> {
+            axiom: "F+G",
+            variables_to_draw: HashSet::from_iter(['F', 'G']),
+            angle: Decimal::HALF_PI,
+            ..Default::default()
+        };
+        let actual = l_system
+            .to_svg_string(&SvgOptions {
+                variable_colors: HashMap::from_iter([('G', "green".to_string())]),
+                adaptive_stroke_color: true,
+                ..Default::default()
+            })
+            .unwrap();
+        assert!(actual.contains("@media (prefers-color-scheme: light)"));
+        assert!(actual.contains("@media (prefers-color-scheme: dark)"));
+        let paths = actual
+            .lines()
+            .filter(|l| l.starts_with("<path"))
+            .collect::<Vec<_>>();
+        assert!(paths[0].starts_with(r#"<path class="lsys-adaptive-stroke" "#));
+        assert!(paths[1].starts_with(r#"<path fill="none""#));
+        assert!(paths[1].contains(r#"stroke="green""#));
+
+        // Only the paths drawn here are restyled when the SVG is inlined into a page
+        let style = &actual[actual.find("<style>").unwrap()..actual.find("</style>").unwrap()];
+        let selectors = style
+            .split(['{', '}'])
+            .map(str::trim)
+            .filter(|rule| !rule.is_empty() && !rule.starts_with('@') && !rule.contains(':'))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            selectors,
+            [".lsys-adaptive-stroke", ".lsys-adaptive-stroke"],
+            "{style}"
+        );
+        let polylines = l_system
+            .to_svg_string(&SvgOptions {
+                adaptive_stroke_color: true,
+                polylines: true,
+                ..Default::default()
+            })
+            .unwrap();
+        assert!(polylines.contains(r#"<polyline class="lsys-adaptive-stroke" "#));
+
+        let actual = l_system.to_svg_string(&SvgOptions::default()).unwrap();
+        assert!(!actual.contains("<style>"));
+        assert!(!actual.contains("class="));
+    }
+
+    #[test]
+    fn theme() {
+        let l_system = LSystem::<_, &str> {
+            axiom: "F+G",
+            variables_to_draw: HashSet::from_iter(['F', 'G']),
+            angle: Decimal::HALF_PI,
+            ..Default::default()
+        };
+        let stroke_colors = |theme: Theme| {
+            let actual = l_system
+                .to_svg_string(&SvgOptions {
+                    variable_colors: HashMap::from_iter([('G', "green".to_string())]),
+                    theme,
+                    ..Default::default()
+                })
+                .unwrap();
+            assert!(!actual.contains("<rect"));
+            actual
+                .split(r#" stroke=""#)
+                .skip(1)
+                .map(|color| color[..color.find('"').unwrap()].to_string())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            stroke_colors(Theme::Dark),
+            ["rgb(100%, 100%, 100%)", "green"]
+        );
+        assert_eq!(stroke_colors(Theme::Light), ["rgb(0%, 0%, 0%)", "green"]);
+        assert_eq!(Theme::default(), Theme::Light);
+    }
+
+    #[test]
+    fn iteration_svgs() {
+        let l_system = LSystem {
+            axiom: "F",
+            variables_to_draw: HashSet::from_iter(['F']),
+            angle: Decimal::HALF_PI,
+            iterations: 3,
+            rules: HashMap::from_iter([('F', "F+F-F-F+F")]),
+            ..Default::default()
+        };
+        let frames = l_system.iteration_svgs(&SvgOptions::default()).unwrap();
+        assert_eq!(frames.len(), l_system.iterations + 1);
+
+        let mut last = vec![];
+        l_system.to_svg(&SvgOptions::default(), &mut last).unwrap();
+        assert_eq!(frames.last().unwrap().as_bytes(), last);
+
+        // Scaled to the final drawing rather than its own degenerate bounding box
+        let mut first = vec![];
+        LSystem {
+            iterations: 0,
+            ..l_system
+        }
+        .to_svg(&SvgOptions::default(), &mut first)
+        .unwrap();
+        assert_ne!(frames[0].as_bytes(), first);
+        assert_eq!(path_points(&frames[0]).len(), 2);
+    }
+
+    #[test]
+    fn animation_duration() {
+        let mut actual = vec![];
+        LSystem::<_, &str> {
+            axiom: "F+F+F",
+            variables_to_draw: HashSet::from_iter(['F']),
+            angle: Decimal::HALF_PI,
+            ..Default::default()
+        }
+        .to_svg(
+            &SvgOptions {
+                animation_duration: Some(Decimal::TWO),
+                ..Default::default()
+            },
+            &mut actual,
+        )
+        .unwrap();
+
+        let actual = String::from_utf8(actual).unwrap();
+        assert!(actual.contains(r#"<animate attributeName="stroke-dashoffset""#));
+        assert!(actual.contains(r#"dur="2s""#));
+        // Three sides of the unit square
+        assert!(actual.contains(r#"stroke-dasharray="3""#));
+    }
+
+    #[test]
+    fn polylines() {
+        let l_system = LSystem {
+            axiom: "F",
+            variables_to_draw: HashSet::from_iter(['F']),
+            angle: Decimal::from(25) / Decimal::from(180) * Decimal::PI,
+            iterations: 2,
+            rules: HashMap::from_iter([('F', "F[+F]F[-F]F")]),
+            ..Default::default()
+        };
+        let actual = l_system
+            .to_svg_string(&SvgOptions {
+                polylines: true,
+                ..Default::default()
+            })
+            .unwrap();
+
+        let strokes = l_system.strokes();
+        let pen_down_runs = strokes
+            .iter()
+            .enumerate()
+            .filter(|(i, stroke)| !stroke.is_move && (*i == 0 || strokes[i - 1].is_move))
+            .count();
+        assert!(pen_down_runs > 1);
+        assert_eq!(actual.matches("<polyline").count(), pen_down_runs);
+        assert_eq!(
+            actual.matches(r#"stroke="rgb(0%, 0%, 0%)""#).count(),
+            pen_down_runs
+        );
+        assert!(!actual.contains("<path"));
+    }
+
+    #[test]
+    fn coordinate_precision() {
+        let l_system = LSystem {
+            axiom: "F",
+            variables_to_draw: HashSet::from_iter(['F']),
+            angle: Decimal::from(60) / Decimal::from(180) * Decimal::PI,
+            iterations: 3,
+            rules: HashMap::from_iter([('F', "F+F--F+F")]),
+            ..Default::default()
+        };
+        let precise = l_system.to_svg_string(&SvgOptions::default()).unwrap();
+        let actual = l_system
+            .to_svg_string(&SvgOptions {
+                coordinate_precision: 2,
+                ..Default::default()
+            })
+            .unwrap();
+        assert!(actual.len() < precise.len());
+
+        let (_, d) = actual.split_once(r#" d=""#).unwrap();
+        let (d, _) = d.split_once('"').unwrap();
+        for coordinate in d.split(' ').filter(|token| *token != "M" && *token != "L") {
+            let decimals = coordinate
+                .split_once('.')
+                .map_or(0, |(_, decimals)| decimals.len());
+            assert!(decimals <= 2, "{coordinate}");
+        }
+    }
+
+    #[test]
+    fn collapse_collinear() {
+        let options = SvgOptions {
+            collapse_collinear: true,
+            ..Default::default()
+        };
+        let line_to_count = |axiom: &str, options: &SvgOptions| {
+            LSystem::<_, &str> {
+                axiom,
+                variables_to_draw: HashSet::from_iter(['F']),
+                variables_to_move: HashSet::from_iter(['f']),
+                angle: Decimal::HALF_PI,
+                ..Default::default()
+            }
+            .to_svg_string(options)
+            .unwrap()
+            .matches(" L ")
+            .count()
+        };
+        assert_eq!(line_to_count("FFFF", &SvgOptions::default()), 4);
+        assert_eq!(line_to_count("FFFF", &options), 1);
+        assert_eq!(line_to_count("FF+FF", &options), 2);
+        // Moves break runs
+        assert_eq!(line_to_count("FFfFF", &options), 2);
+    }
+
+    #[test]
+    fn relative_commands() {
+        let l_system = LSystem {
+            axiom: "F",
+            variables_to_draw: HashSet::from_iter(['F']),
+            angle: Decimal::from(25) / Decimal::from(180) * Decimal::PI,
+            iterations: 3,
+            rules: HashMap::from_iter([('F', "F[+F]F[-F]F")]),
+            ..Default::default()
+        };
+        let path = |options: &SvgOptions| {
+            let svg = l_system.to_svg_string(options).unwrap();
+            let (_, d) = svg.split_once(r#" d=""#).unwrap();
+            let (d, _) = d.split_once('"').unwrap();
+            d.split(' ').map(str::to_string).collect::<Vec<_>>()
+        };
+        let coordinate = |token: &String| token.parse::<Decimal>().unwrap();
+
+        let absolute = path(&SvgOptions::default());
+        let relative = path(&SvgOptions {
+            relative_commands: true,
+            ..Default::default()
+        });
+        assert_eq!(absolute.len(), relative.len());
+        assert!(!relative[3..]
+            .iter()
+            .any(|token| token == "L" || token == "M"));
+
+        let mut end = (coordinate(&relative[1]), coordinate(&relative[2]));
+        for delta in relative[3..].chunks(3) {
+            end = (end.0 + coordinate(&delta[1]), end.1 + coordinate(&delta[2]));
+        }
+        let tolerance = Decimal::new(1, 6);
+        let [.., x, y] = absolute.as_slice() else {
+            unreachable!()
+        };
+        assert!((end.0 - coordinate(x)).abs() < tolerance);
+        assert!((end.1 - coordinate(y)).abs() < tolerance);
+    }
+
+    #[test]
+    fn fill() {
+        let l_system = LSystem::<_, &str> {
+            axiom: "F+F+F+F",
+            variables_to_draw: HashSet::from_iter(['F']),
+            angle: Decimal::HALF_PI,
+            ..Default::default()
+        };
+        let actual = l_system
+            .to_svg_string(&SvgOptions {
+                fill: Some("red".to_string()),
+                ..Default::default()
+            })
+            .unwrap();
+        assert!(actual.contains(r#"fill="red""#));
+        assert!(actual.contains(r#" Z""#));
+        assert!(actual.contains(r#"stroke="rgb(0%, 0%, 0%)""#));
+
+        let actual = l_system
+            .to_svg_string(&SvgOptions {
+                fill: Some("red".to_string()),
+                stroke: false,
+                ..Default::default()
+            })
+            .unwrap();
+        assert!(actual.contains(r#"fill="red""#));
+        assert!(actual.contains(r#"stroke="none""#));
+    }
+
+    #[test]
+    fn fill_rule() {
+        let l_system = LSystem::<_, &str> {
+            axiom: "F+F+F+F",
+            variables_to_draw: HashSet::from_iter(['F']),
+            angle: Decimal::HALF_PI,
+            ..Default::default()
+        };
+        let options = SvgOptions {
+            fill: Some("red".to_string()),
+            ..Default::default()
+        };
+        let actual = l_system.to_svg_string(&options).unwrap();
+        assert!(actual.contains(r#"fill-rule="nonzero""#));
+
+        let actual = l_system
+            .to_svg_string(&SvgOptions {
+                fill_rule: FillRule::EvenOdd,
+                ..options
+            })
+            .unwrap();
+        assert!(actual.contains(r#"fill-rule="evenodd""#));
+    }
+
+    #[test]
+    fn shape_rendering() {
+        let l_system = LSystem::<_, &str> {
+            axiom: "F+F+F+F",
+            variables_to_draw: HashSet::from_iter(['F']),
+            angle: Decimal::HALF_PI,
+            ..Default::default()
+        };
+        let actual = l_system.to_svg_string(&SvgOptions::default()).unwrap();
+        assert!(!actual.contains("shape-rendering"));
+
+        let actual = l_system
+            .to_svg_string(&SvgOptions {
+                shape_rendering: Some(ShapeRendering::CrispEdges),
+                ..Default::default()
+            })
+            .unwrap();
+        let path = actual.lines().find(|l| l.starts_with("<path")).unwrap();
+        assert!(path.contains(r#"shape-rendering="crispEdges""#), "{path}");
+    }
+
+    #[test]
+    fn width_taper() {
+        let actual = LSystem::<_, &str> {
+            axiom: "F[+F[-F]]F",
+            variables_to_draw: HashSet::from_iter(['F']),
+            angle: Decimal::HALF_PI / Decimal::TWO,
+            ..Default::default()
+        }
+        .to_svg_string(&SvgOptions {
+            width_taper: Decimal::new(5, 1),
+            ..Default::default()
+        })
+        .unwrap();
+
+        let stroke_widths = actual
+            .split(r#"stroke-width=""#)
+            .skip(1)
+            .map(|rest| rest.split_once('"').unwrap().0.parse::<Decimal>().unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            stroke_widths,
+            [Decimal::new(1, 2), Decimal::new(5, 3), Decimal::new(25, 4)]
+        );
+
+        // Deeply nested branches saturate instead of overflowing
+        let deep = LSystem::<_, &str> {
+            axiom: "[F]",
+            variables_to_draw: HashSet::from_iter(['F']),
+            iterations: 200,
+            rules: HashMap::from_iter([('F', "[F]")]),
+            ..Default::default()
+        };
+        for factor in [Decimal::TWO, Decimal::new(5, 1)] {
+            assert!(deep
+                .to_svg_string(&SvgOptions {
+                    width_taper: factor,
+                    depth_opacity_fade: Some(factor),
+                    ..Default::default()
+                })
+                .is_ok());
+        }
+    }
+
+    #[test]
+    fn branch_groups() {
+        let groups = |axiom: &str| {
+            let svg = LSystem::<_, &str> {
+                axiom,
+                variables_to_draw: HashSet::from_iter(['F']),
+                angle: Decimal::HALF_PI / Decimal::TWO,
+                ..Default::default()
+            }
+            .to_svg_string(&SvgOptions {
+                branch_groups: true,
+                ..Default::default()
+            })
+            .unwrap();
+            svg.lines()
+                .filter(|line| *line == "<g>" || *line == "</g>")
+                .collect::<String>()
+        };
+        assert_eq!(groups("F[+F[-F]]F"), "<g><g></g></g>");
+        assert_eq!(groups("F[+F][-F]F"), "<g></g><g></g>");
+    }
+
+    #[test]
+    fn title_and_description() {
+        let actual = LSystem::<_, &str> {
+            axiom: "F",
+            variables_to_draw: HashSet::from_iter(['F']),
+            ..Default::default()
+        }
+        .to_svg_string(&SvgOptions {
+            title: Some("Koch & <friends>".to_string()),
+            description: Some("A curve".to_string()),
+            ..Default::default()
+        })
+        .unwrap();
+        let mut lines = actual.lines().skip(2);
+        assert_eq!(
+            lines.next(),
+            Some("<title>Koch &amp; &lt;friends&gt;</title>")
+        );
+        assert_eq!(lines.next(), Some("<desc>A curve</desc>"));
+    }
+
+    #[test]
+    fn xml_escape() {
+        let title = r#"<Koch> & "friends""#;
+        let color = r##"url("#gradient")"##;
+        let actual = LSystem::<_, &str> {
+            axiom: "F.",
+            variables_to_draw: HashSet::from_iter(['F']),
+            ..Default::default()
+        }
+        .to_svg_string(&SvgOptions {
+            title: Some(title.to_string()),
+            variable_colors: HashMap::from_iter([('F', color.to_string())]),
+            fill: Some(color.to_string()),
+            ..Default::default()
+        })
+        .unwrap();
+
+        let document = roxmltree::Document::parse(&actual).unwrap();
+        let find = |name: &str| {
+            document
+                .descendants()
+                .find(|node| node.has_tag_name(name))
+                .unwrap()
+        };
+        assert_eq!(find("title").text(), Some(title));
+        assert_eq!(find("path").attribute("stroke"), Some(color));
+        assert_eq!(find("path").attribute("fill"), Some(color));
+        // Dots are colored like the line they sit on
+        assert_eq!(find("circle").attribute("fill"), Some(color));
+    }
+
+    #[test]
+    fn content_transform() {
+        let l_system = LSystem::<_, &str> {
+            axiom: "F+F",
+            variables_to_draw: HashSet::from_iter(['F']),
+            angle: Decimal::HALF_PI,
+            ..Default::default()
+        };
+        let actual = l_system.to_svg_string(&SvgOptions::default()).unwrap();
+        assert!(actual.contains(r#"transform="matrix(100, 0, 0, 100, 0, 0)""#));
+
+        let actual = l_system
+            .to_svg_string(&SvgOptions {
+                content_scale: Some((Decimal::from(80), Decimal::from(60))),
+                content_offset: (Decimal::TEN, Decimal::new(205, 1)),
+                ..Default::default()
+            })
+            .unwrap();
+        assert!(
+            actual.contains(r#"transform="matrix(80, 0, 0, 60, 10, 20.5)""#),
+            "{actual}"
+        );
+        // The canvas keeps its size
+        assert!(actual.contains(r#"viewBox="0 0 100 100""#));
+        // 1 unit of the scaled drawing
+        assert!(actual.contains(&format!(
+            r#"stroke-width="{}""#,
+            Decimal::ONE / Decimal::from(60)
+        )));
+    }
+
+    #[test]
+    fn margin() {
+        let mut actual = vec![];
+        LSystem {
+            axiom: "F",
+            variables_to_draw: HashSet::from_iter(['F']),
+            angle: Decimal::HALF_PI,
+            iterations: 3,
+            rules: HashMap::from_iter([('F', "F+F-F-F+F")]),
+            ..Default::default()
+        }
+        .to_svg(
+            &SvgOptions {
+                margin: Decimal::new(1, 1),
+                ..Default::default()
+            },
+            &mut actual,
+        )
+        .unwrap();
+
+        let coordinates = path_points(&String::from_utf8(actual).unwrap())
+            .into_iter()
+            .flat_map(|(x, y)| [x, y])
+            .collect::<Vec<_>>();
+        assert!(!coordinates.is_empty());
+        assert!(coordinates.contains(&Decimal::new(1, 1)));
+        assert!(coordinates.contains(&Decimal::new(9, 1)));
+        for coordinate in coordinates {
+            assert!(
+                (Decimal::new(1, 1)..=Decimal::new(9, 1)).contains(&coordinate),
+                "{coordinate} is outside of the margin"
+            );
+        }
+
+        for margin in [Decimal::new(5, 1), Decimal::NEGATIVE_ONE] {
+            for tight_view_box in [false, true] {
+                assert!(matches!(
+                    LSystem::<_, &str> {
+                        axiom: "F",
+                        variables_to_draw: HashSet::from_iter(['F']),
+                        ..Default::default()
+                    }
+                    .to_svg_string(&SvgOptions {
+                        margin,
+                        tight_view_box,
+                        ..Default::default()
+                    }),
+                    Err(RenderError::InvalidMargin(invalid)) if invalid == margin
+                ));
+            }
+        }
+    }
+
+    #[test]
+    fn preserve_aspect_ratio() {
+        let square = LSystem::<_, &str> {
+            axiom: "F+F+F+F",
+            variables_to_draw: HashSet::from_iter(['F']),
+            angle: Decimal::HALF_PI,
+            ..Default::default()
+        };
+        let extents = |preserve_aspect_ratio| {
+            let mut actual = vec![];
+            square
+                .to_svg(
+                    &SvgOptions {
+                        width: Decimal::from(200),
+                        height: Decimal::ONE_HUNDRED,
+                        preserve_aspect_ratio,
+                        ..Default::default()
+                    },
+                    &mut actual,
+                )
+                .unwrap();
+            let points = path_points(&String::from_utf8(actual).unwrap());
+            let xs = || points.iter().map(|point| point.0);
+            let ys = || points.iter().map(|point| point.1);
+            (
+                (xs().min().unwrap(), xs().max().unwrap()),
+                (ys().min().unwrap(), ys().max().unwrap()),
+            )
+        };
+
+        // Stretched to fill the canvas
+        let ((min_x, max_x), (min_y, max_y)) = extents(false);
+        assert_eq!((max_x - min_x).round_dp(5), Decimal::ONE);
+        assert_eq!((max_y - min_y).round_dp(5), Decimal::ONE);
+
+        // Half as wide relative to the canvas, so it stays square, and centered
+        let ((min_x, max_x), (min_y, max_y)) = extents(true);
+        assert_eq!(min_x.round_dp(5), Decimal::new(25, 2));
+        assert_eq!(max_x.round_dp(5), Decimal::new(75, 2));
+        assert_eq!((max_y - min_y).round_dp(5), Decimal::ONE);
+
+        // An empty canvas does not divide by zero
+        for (width, height) in [(Decimal::ZERO, Decimal::TEN), (Decimal::TEN, Decimal::ZERO)] {
+            for tight_view_box in [false, true] {
+                assert!(square
+                    .to_svg_string(&SvgOptions {
+                        width,
+                        height,
+                        preserve_aspect_ratio: true,
+                        tight_view_box,
+                        ..Default::default()
+                    })
+                    .is_ok());
+            }
+        }
+    }
+
+    #[test]
+    fn tight_view_box() {
+        let mut actual = vec![];
+        LSystem::<_, &str> {
+            axiom: "F+F+FF",
+            variables_to_draw: HashSet::from_iter(['F']),
+            angle: Decimal::HALF_PI,
+            initial_heading: Decimal::ZERO,
+            ..Default::default()
+        }
+        .to_svg(
+            &SvgOptions {
+                tight_view_box: true,
+                ..Default::default()
+            },
+            &mut actual,
+        )
+        .unwrap();
+
+        let actual = String::from_utf8(actual).unwrap();
+        assert!(!actual.contains("transform"));
+        let view_box = actual.split(r#"viewBox=""#).nth(1).unwrap();
+        let view_box = view_box[..view_box.find('"').unwrap()]
+            .split(' ')
+            .map(|value| Decimal::from_str_exact(value).unwrap())
+            .collect::<Vec<_>>();
+        let points = path_points(&actual);
+        let xs = || points.iter().map(|point| point.0);
+        let ys = || points.iter().map(|point| point.1);
+        let (min_x, min_y) = (xs().min().unwrap(), ys().min().unwrap());
+        assert_eq!(
+            view_box,
+            [
+                min_x,
+                min_y,
+                xs().max().unwrap() - min_x,
+                ys().max().unwrap() - min_y
+            ]
+        );
+        assert_eq!(view_box[0].round_dp(5), -Decimal::ONE);
+        assert_eq!(view_box[2].round_dp(5), Decimal::TWO);
+    }
+
+    #[test]
+    fn no_trailing_zeros() {
+        let l_system = LSystem::<_, &str> {
+            axiom: "F+F[+F.]-F-F",
+            variables_to_draw: HashSet::from_iter(['F']),
+            angle: Decimal::PI / Decimal::from(3),
+            ..Default::default()
+        };
+        for options in [
+            SvgOptions::default(),
+            SvgOptions {
+                width: Decimal::new(1000, 1),
+                height: Decimal::new(5000, 2),
+                stroke_width: Some(Decimal::new(500, 3)),
+                ..Default::default()
+            },
+            SvgOptions {
+                tight_view_box: true,
+                relative_commands: true,
+                leaf_dot_radius: Some(Decimal::new(10, 1)),
+                ..Default::default()
+            },
+            SvgOptions {
+                unit_length: Some(Decimal::new(20, 1)),
+                polylines: true,
+                animation_duration: Some(Decimal::new(30, 1)),
+                ..Default::default()
+            },
+        ] {
+            let actual = l_system.to_svg_string(&options).unwrap();
+            // Skipping the XML declaration, which has a version of 1.0
+            for line in actual.lines().skip(1) {
+                // Numbers in attribute values, which are between every other quote
+                for number in line
+                    .split('"')
+                    .skip(1)
+                    .step_by(2)
+                    .flat_map(|value| value.split([' ', ',', '(', ')']))
+                    // Without units, like the `mm` in widths or `s` in durations
+                    .map(|value| value.trim_end_matches(|c: char| c.is_ascii_alphabetic()))
+                    .filter(|value| value.parse::<Decimal>().is_ok())
+                {
+                    assert!(
+                        !number.contains('.') || !number.ends_with(['0', '.']),
+                        "{number} in {line}"
+                    );
+                    assert_ne!(number, "-0", "{line}");
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn normalize() {
+        let l_system = LSystem::<_, &str> {
+            axiom: "F+F+FF",
+            variables_to_draw: HashSet::from_iter(['F']),
+            angle: Decimal::HALF_PI,
+            initial_heading: Decimal::ZERO,
+            ..Default::default()
+        };
+        let actual = l_system
+            .to_svg_string(&SvgOptions {
+                normalize: false,
+                ..Default::default()
+            })
+            .unwrap();
+
+        assert!(!actual.contains("transform"));
+        assert!(!actual.contains("preserveAspectRatio"));
+        let strokes = l_system.strokes();
+        let expected = std::iter::once(strokes[0].start)
+            .chain(strokes.iter().map(|stroke| stroke.end))
+            .map(|(x, y)| (x.round_dp(7), y.round_dp(7)))
+            .collect::<Vec<_>>();
+        assert_eq!(path_points(&actual), expected);
+        assert!(actual.contains(r#"viewBox="-1 0 2 1""#), "{actual}");
+    }
+
+    #[test]
+    fn flip_y() {
+        let l_system = LSystem::<_, &str> {
+            axiom: "FFF",
+            variables_to_draw: HashSet::from_iter(['F']),
+            // Up, with y growing upward
+            initial_heading: Decimal::HALF_PI,
+            ..Default::default()
+        };
+        let ys = |options: &SvgOptions| {
+            path_points(&l_system.to_svg_string(options).unwrap())
+                .into_iter()
+                .map(|(_, y)| y)
+                .collect::<Vec<_>>()
+        };
+
+        let ys_down = ys(&SvgOptions::default());
+        assert!(ys_down.windows(2).all(|ys| ys[0] < ys[1]), "{ys_down:?}");
+        let ys_up = ys(&SvgOptions {
+            flip_y: true,
+            ..Default::default()
+        });
+        assert!(ys_up.windows(2).all(|ys| ys[0] > ys[1]), "{ys_up:?}");
+    }
+
+    #[test]
+    fn to_svg_range() {
+        let koch = LSystem {
+            axiom: "F",
+            variables_to_draw: HashSet::from_iter(['F']),
+            angle: Decimal::HALF_PI,
+            iterations: 3,
+            rules: HashMap::from_iter([('F', "F+F-F-F+F")]),
+            ..Default::default()
+        };
+        let len = koch.symbols().count();
+        let segments = |start, end| {
+            let mut svg = vec![];
+            koch.to_svg_range(start, end, &SvgOptions::default(), &mut svg)
+                .unwrap();
+            String::from_utf8(svg).unwrap().matches(" L ").count()
+        };
+
+        let full = segments(0, len);
+        assert_eq!(full, 125);
+        assert_eq!(segments(0, len / 2), 62);
+        assert_eq!(segments(len / 2, len), 63);
+        assert_eq!(segments(0, usize::MAX), full);
+        assert_eq!(segments(len, len + 10), 0);
+
+        let mut svg = vec![];
+        koch.to_svg_range(0, len, &SvgOptions::default(), &mut svg)
+            .unwrap();
+        assert_eq!(
+            String::from_utf8(svg).unwrap(),
+            koch.to_svg_string(&SvgOptions::default()).unwrap()
+        );
+    }
+
+    #[test]
+    fn to_svg_with_progress() {
+        let l_system = LSystem {
+            axiom: "F",
+            variables_to_draw: HashSet::from_iter(['F']),
+            angle: Decimal::HALF_PI,
+            iterations: 6,
+            rules: HashMap::from_iter([('F', "F+F-F-F+F")]),
+            ..Default::default()
+        };
+        let mut calls = vec![];
+        let mut actual = vec![];
+        l_system
+            .to_svg_with_progress(&SvgOptions::default(), &mut actual, |progress| {
+                calls.push(progress)
+            })
+            .unwrap();
+        assert_eq!(
+            String::from_utf8(actual).unwrap(),
+            l_system.to_svg_string(&SvgOptions::default()).unwrap()
+        );
+
+        let iterations = calls
+            .iter()
+            .filter_map(|progress| match progress {
+                Progress::Expanding {
+                    iteration,
+                    iterations,
+                } => {
+                    assert_eq!(*iterations, 6);
+                    Some(*iteration)
+                }
+                Progress::Drawing { .. } => None,
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(iterations, (1..=6).collect::<Vec<_>>());
+
+        let drawn = calls
+            .iter()
+            .skip(iterations.len())
+            .map(|progress| match progress {
+                Progress::Drawing {
+                    walked,
+                    len,
+                    strokes,
+                } => (*walked, *len, *strokes),
+                Progress::Expanding { .. } => panic!("expanding after drawing: {calls:?}"),
+            })
+            .collect::<Vec<_>>();
+        assert!(drawn.len() > 1, "{drawn:?}");
+        assert!(
+            drawn
+                .windows(2)
+                .all(|pair| pair[0].0 < pair[1].0 && pair[0].2 <= pair[1].2),
+            "{drawn:?}"
+        );
+        let &(walked, len, strokes) = drawn.last().unwrap();
+        assert_eq!(walked, len);
+        assert_eq!(strokes, l_system.strokes().len());
+    }
+
+    #[test]
+    fn to_svg_cancellable() {
+        let l_system = LSystem {
+            axiom: "F",
+            variables_to_draw: HashSet::from_iter(['F']),
+            angle: Decimal::HALF_PI,
+            iterations: 6,
+            rules: HashMap::from_iter([('F', "F+F-F-F+F")]),
+            ..Default::default()
+        };
+        // The 6 iterations check 1, 1, 1, 1, 1 and 2 times, then the walk 8 times. Trip the flag
+        // partway through the last iteration, and partway through the walk
+        for (trip, expected) in [(6, 7), (8, 9)] {
+            let checks = AtomicUsize::new(0);
+            let cancelled = AtomicBool::new(false);
+            let mut svg = vec![];
+            let result = l_system.to_svg_cancellable(&SvgOptions::default(), &mut svg, || {
+                if checks.fetch_add(1, Ordering::Relaxed) == trip {
+                    cancelled.store(true, Ordering::Relaxed);
+                }
+                cancelled.load(Ordering::Relaxed)
+            });
+            assert!(matches!(result, Err(RenderError::Cancelled)), "{result:?}");
+            assert_eq!(checks.load(Ordering::Relaxed), expected);
+            assert!(svg.is_empty());
+        }
+
+        let mut svg = vec![];
+        l_system
+            .to_svg_cancellable(&SvgOptions::default(), &mut svg, || false)
+            .unwrap();
+        assert_eq!(
+            String::from_utf8(svg).unwrap(),
+            l_system.to_svg_string(&SvgOptions::default()).unwrap()
+        );
+    }
+
+    #[test]
+    fn unit_length() {
+        let l_system = LSystem::<_, &str> {
+            axiom: "F+F+FF",
+            variables_to_draw: HashSet::from_iter(['F']),
+            angle: Decimal::HALF_PI,
+            initial_heading: Decimal::ZERO,
+            ..Default::default()
+        };
+        let span = |unit_length: Decimal| {
+            let mut actual = vec![];
+            l_system
+                .to_svg(
+                    &SvgOptions {
+                        unit_length: Some(unit_length),
+                        ..Default::default()
+                    },
+                    &mut actual,
+                )
+                .unwrap();
+            let actual = String::from_utf8(actual).unwrap();
+            assert!(!actual.contains("transform"));
+            let points = path_points(&actual);
+            let xs = || points.iter().map(|point| point.0);
+            let ys = || points.iter().map(|point| point.1);
+            assert_eq!(xs().min().unwrap(), Decimal::ZERO);
+            (
+                xs().max().unwrap() - xs().min().unwrap(),
+                ys().max().unwrap() - ys().min().unwrap(),
+            )
+        };
+        let span_5 = span(Decimal::from(5));
+        assert_eq!(span_5, (Decimal::from(10), Decimal::from(5)));
+        assert_eq!(
+            span(Decimal::TEN),
+            (span_5.0 * Decimal::TWO, span_5.1 * Decimal::TWO)
+        );
+
+        let mut actual = vec![];
+        l_system
+            .to_svg(
+                &SvgOptions {
+                    unit_length: Some(Decimal::from(5)),
+                    tight_view_box: true,
+                    ..Default::default()
+                },
+                &mut actual,
+            )
+            .unwrap();
+        let actual = String::from_utf8(actual).unwrap();
+        assert!(
+            actual.contains(r#"width="10mm" height="5mm" viewBox="-5 0 10 5""#),
+            "{actual}"
+        );
+    }
+
+    #[test]
+    fn path_starts_at_the_start_of_the_walk() {
+        let mut actual = vec![];
+        LSystem::<_, &str> {
+            axiom: "FF+F",
+            variables_to_draw: HashSet::from_iter(['F']),
+            angle: Decimal::HALF_PI,
+            initial_heading: Decimal::ZERO,
+            ..Default::default()
+        }
+        .to_svg(&SvgOptions::default(), &mut actual)
+        .unwrap();
+
+        let actual = String::from_utf8(actual).unwrap();
+        let half = Decimal::new(5, 1);
+        assert_eq!(
+            path_points(&actual),
+            [
+                (Decimal::ZERO, Decimal::ZERO),
+                (half, Decimal::ZERO),
+                (Decimal::ONE, Decimal::ZERO),
+                (Decimal::ONE, Decimal::ONE)
+            ]
+        );
+    }
+
+    #[test]
+    fn path_starts_with_a_move_to_the_origin() {
+        let mut actual = vec![];
+        LSystem::<_, &str> {
+            axiom: "fF+F'F",
+            variables_to_draw: HashSet::from_iter(['F']),
+            variables_to_move: HashSet::from_iter(['f']),
+            angle: Decimal::HALF_PI,
+            initial_heading: Decimal::ZERO,
+            ..Default::default()
+        }
+        .to_svg(
+            &SvgOptions {
+                palette: vec!["green".to_string(), "brown".to_string()],
+                ..Default::default()
+            },
+            &mut actual,
+        )
+        .unwrap();
+
+        let actual = String::from_utf8(actual).unwrap();
+        let paths = actual
+            .lines()
+            .filter(|l| l.starts_with("<path"))
+            .collect::<Vec<_>>();
+        assert_eq!(paths.len(), 2);
+        for path in paths {
+            let d = path.split(" d=\"").nth(1).unwrap();
+            assert!(d.starts_with("M 0 0 M "), "{d}");
+        }
+    }
+
+    #[test]
+    fn palette() {
+        let mut actual = vec![];
+        LSystem {
+            axiom: "F+F'F+F'F",
+            variables_to_draw: HashSet::from_iter(['F']),
+            angle: Decimal::HALF_PI,
+            iterations: 0,
+            rules: HashMap::<char, &str>::new(),
+            ..Default::default()
+        }
+        .to_svg(
+            &SvgOptions {
+                palette: vec!["green".to_string(), "brown".to_string()],
+                ..Default::default()
+            },
+            &mut actual,
+        )
+        .unwrap();
+
+        let actual = String::from_utf8(actual).unwrap();
+        let paths = actual
+            .lines()
+            .filter(|l| l.starts_with("<path"))
+            .collect::<Vec<_>>();
+        assert_eq!(paths.len(), 2);
+        assert!(paths[0].contains(r#"stroke="green""#));
+        assert_eq!(paths[0].matches(" L ").count(), 3);
+        assert!(paths[1].contains(r#"stroke="brown""#));
+        assert_eq!(paths[1].matches(" L ").count(), 2);
+    }
+
+    #[test]
+    fn abop_width_and_color() {
+        // Branches get thinner with `!` and turn brown with `;`, as in ABOP
+        let mut actual = vec![];
+        LSystem {
+            axiom: "F",
+            variables_to_draw: HashSet::from_iter(['F']),
+            iterations: 1,
+            rules: HashMap::from_iter([('F', "F[+!F;F][-!F]F")]),
+            ..Default::default()
+        }
+        .with_angle_degrees(25)
+        .to_svg(
+            &SvgOptions {
+                stroke_width: Some(Decimal::TWO),
+                palette: vec!["green".to_string(), "brown".to_string()],
+                width_palette: vec![Decimal::ONE, Decimal::new(5, 1)],
+                ..Default::default()
+            },
+            &mut actual,
+        )
+        .unwrap();
+
+        let actual = String::from_utf8(actual).unwrap();
+        let paths = actual
+            .lines()
+            .filter(|l| l.starts_with("<path"))
+            .map(|path| {
+                let attribute = |name: &str| {
+                    let value =
+                        &path[path.find(&format!(r#" {name}=""#)).unwrap() + name.len() + 3..];
+                    value[..value.find('"').unwrap()].to_string()
+                };
+                (
+                    attribute("stroke"),
+                    attribute("stroke-width"),
+                    path.matches(" L ").count(),
+                )
+            })
+            .collect::<Vec<_>>();
+        // The width is restored at the end of each branch, but the color is not
+        assert_eq!(
+            paths,
+            [
+                ("green".to_string(), "2".to_string(), 1),
+                ("green".to_string(), "1".to_string(), 1),
+                ("brown".to_string(), "1".to_string(), 2),
+                ("brown".to_string(), "2".to_string(), 1),
+            ]
+        );
+    }
+
+    #[test]
+    fn gpl_palette() {
+        let palette = lsys::parse_gpl_palette(
+            "GIMP Palette
+Name: Autumn
+Columns: 3
+# Leaves
+ 34 139  34	Forest Green
+139  69  19	Saddle Brown
+
+255 215   0
+",
+        )
+        .unwrap();
+        assert_eq!(
+            palette,
+            ["rgb(34, 139, 34)", "rgb(139, 69, 19)", "rgb(255, 215, 0)"]
+        );
+
+        assert!(matches!(
+            lsys::parse_gpl_palette("Name: Autumn\n0 0 0"),
+            Err(PaletteError::MissingHeader)
+        ));
+        assert!(matches!(
+            lsys::parse_gpl_palette("GIMP Palette\n0 0 0\n0 256 0 Too green"),
+            Err(PaletteError::InvalidColor { line: 3 })
+        ));
+
+        // Colors past the end of the palette wrap around
+        let svg = LSystem::<_, &str> {
+            axiom: "F;F;F;F",
+            variables_to_draw: HashSet::from_iter(['F']),
+            ..Default::default()
+        }
+        .to_svg_string(&SvgOptions {
+            palette,
+            ..Default::default()
+        })
+        .unwrap();
+        let strokes = svg
+            .lines()
+            .filter(|l| l.starts_with("<path"))
+            .map(|path| {
+                (
+                    path.contains("rgb(34, 139, 34)"),
+                    path.matches(" L ").count(),
+                )
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(strokes, [(true, 2), (false, 1), (false, 1)]);
+    }
+
+    #[test]
+    fn branch_colorize() {
+        let l_system = LSystem::<_, &str> {
+            axiom: "F[+F[-F]][F][-F]F[+F]F",
+            variables_to_draw: HashSet::from_iter(['F']),
+            angle: Decimal::new(4, 1),
+            ..Default::default()
+        };
+        let options = SvgOptions {
+            branch_colorize: true,
+            branch_groups: true,
+            ..Default::default()
+        };
+        let svg = l_system.to_svg_string(&options).unwrap();
+        let colors = svg
+            .lines()
+            .filter(|l| l.starts_with("<path"))
+            .map(|path| {
+                let stroke = &path[path.find(r#" stroke=""#).unwrap() + 9..];
+                stroke[..stroke.find('"').unwrap()].to_string()
+            })
+            .collect::<Vec<_>>();
+        let branch_colors = colors
+            .iter()
+            .filter(|color| color.starts_with("hsl("))
+            .collect::<HashSet<_>>();
+        // The branch inside the first branch shares its color
+        assert_eq!(branch_colors.len(), 4, "{colors:?}");
+        assert!(colors.contains(&"rgb(0%, 0%, 0%)".to_string()));
+        assert_eq!(svg, l_system.to_svg_string(&options).unwrap());
+
+        let svg = l_system.to_svg_string(&SvgOptions::default()).unwrap();
+        assert!(!svg.contains("hsl("));
+    }
+
+    #[test]
+    fn stroke_opacity() {
+        let l_system = LSystem::<_, &str> {
+            axiom: "F[+F[+F]]",
+            variables_to_draw: HashSet::from_iter(['F']),
+            angle: Decimal::new(4, 1),
+            ..Default::default()
+        };
+        let opacities = |options: &SvgOptions| {
+            let svg = l_system.to_svg_string(options).unwrap();
+            svg.lines()
+                .filter(|l| l.starts_with("<path"))
+                .map(|path| {
+                    let opacity = &path[path.find(r#"stroke-opacity=""#).unwrap() + 16..];
+                    opacity[..opacity.find('"').unwrap()].parse().unwrap()
+                })
+                .collect::<Vec<Decimal>>()
+        };
+
+        assert_eq!(opacities(&SvgOptions::default()), [Decimal::ONE]);
+        assert_eq!(
+            opacities(&SvgOptions {
+                stroke_opacity: Decimal::new(8, 1),
+                ..Default::default()
+            }),
+            [Decimal::new(8, 1)]
+        );
+        assert_eq!(
+            opacities(&SvgOptions {
+                stroke_opacity: Decimal::new(8, 1),
+                depth_opacity_fade: Some(Decimal::new(5, 1)),
+                ..Default::default()
+            }),
+            [Decimal::new(8, 1), Decimal::new(4, 1), Decimal::new(2, 1)]
+        );
+    }
+
+    #[test]
+    fn xml_declaration() {
+        let l_system = LSystem::<_, &str> {
+            axiom: "F",
+            variables_to_draw: HashSet::from_iter(['F']),
+            ..Default::default()
+        };
+        let svg = l_system.to_svg_string(&SvgOptions::default()).unwrap();
+        assert!(svg.starts_with("<?xml "));
+
+        let inline = l_system
+            .to_svg_string(&SvgOptions {
+                xml_declaration: false,
+                ..Default::default()
+            })
+            .unwrap();
+        assert!(!inline.contains("<?xml"));
+        assert!(inline.starts_with("<svg "));
+        assert_eq!(svg.split_once('\n').unwrap().1, inline);
+    }
+
+    #[test]
+    fn deterministic_output() {
+        // Each call builds new maps, which iterate in a different order
+        let render = || {
+            let l_system = LSystem {
+                axiom: "A+B+C+D+E",
+                variables_to_draw: HashSet::from_iter("ABCDE".chars()),
+                angle: Decimal::HALF_PI,
+                iterations: 2,
+                rules: HashMap::from_iter([
+                    ('A', "AB"),
+                    ('B', "C[+D]"),
+                    ('C', "E-A"),
+                    ('D', "DD"),
+                    ('E', "B"),
+                ]),
+                ..Default::default()
+            };
+            let options = SvgOptions {
+                variable_colors: HashMap::from_iter(
+                    ["red", "green", "blue", "orange", "purple"]
+                        .into_iter()
+                        .zip("ABCDE".chars())
+                        .map(|(color, variable)| (variable, color.to_string())),
+                ),
+                width_taper: Decimal::new(5, 1),
+                ..Default::default()
+            };
+            let svg = l_system.to_svg_string(&options).unwrap();
+            #[cfg(feature = "serde")]
+            let svg = svg + &serde_json::to_string(&(l_system, options)).unwrap();
+            svg
+        };
+        let expected = render();
+        for _ in 0..10 {
+            assert_eq!(render(), expected);
+        }
+
+        for _ in 0..10 {
+            assert_eq!(
+                LSystem::builder()
+                    .axiom("A")
+                    .rule('A', "X")
+                    .rule('B', "Y")
+                    .rule('C', "Z")
+                    .build()
+                    .unwrap_err(),
+                LSystemError::MissingRule('X')
+            );
+        }
+    }
+
+    #[test]
+    fn strokes() {
+        let strokes = LSystem {
+            axiom: "F",
+            variables_to_draw: HashSet::from_iter(['F']),
+            angle: Decimal::HALF_PI,
+            iterations: 1,
+            rules: HashMap::from_iter([('F', "F+F-F-F+F")]),
+            ..Default::default()
+        }
+        .strokes();
+        assert_eq!(strokes.len(), 5);
+        assert!(strokes.iter().all(|stroke| !stroke.is_move));
+        assert_eq!(strokes[0].start, (Decimal::ZERO, Decimal::ZERO));
+        for window in strokes.windows(2) {
+            assert_eq!(window[0].end, window[1].start);
+        }
+    }
+
+    #[test]
+    fn initial_heading() {
+        let up = LSystem::<_, &str> {
+            axiom: "F+F",
+            variables_to_draw: HashSet::from_iter(['F']),
+            angle: Decimal::HALF_PI,
+            ..Default::default()
+        }
+        .strokes();
+        let right = LSystem::<_, &str> {
+            axiom: "F+F",
+            variables_to_draw: HashSet::from_iter(['F']),
+            angle: Decimal::HALF_PI,
+            initial_heading: Decimal::ZERO,
+            ..Default::default()
+        }
+        .strokes();
+
+        assert_eq!(up.len(), right.len());
+        for (up, right) in up.iter().zip(&right) {
+            // Rotated a quarter turn clockwise
+            assert_eq!(up.end.0.round_dp(10), right.end.1.round_dp(10));
+            assert_eq!(up.end.1.round_dp(10), (-right.end.0).round_dp(10));
+        }
+    }
+
+    #[test]
+    fn origin() {
+        let l_system = LSystem::<_, &str> {
+            axiom: "F+F[-F]F",
+            variables_to_draw: HashSet::from_iter(['F']),
+            angle: Decimal::HALF_PI,
+            origin: (Decimal::ONE, Decimal::TWO),
+            ..Default::default()
+        };
+        let moved = LSystem {
+            origin: (Decimal::from(-3), Decimal::new(55, 1)),
+            ..l_system.clone()
+        };
+        let delta = (Decimal::from(-4), Decimal::new(35, 1));
+
+        let (strokes, moved_strokes) = (l_system.strokes(), moved.strokes());
+        assert_eq!(strokes[0].start, l_system.origin);
+        assert_eq!(strokes.len(), moved_strokes.len());
+        for (stroke, moved) in strokes.iter().zip(&moved_strokes) {
+            assert_eq!(moved.start.0 - stroke.start.0, delta.0);
+            assert_eq!(moved.start.1 - stroke.start.1, delta.1);
+            assert_eq!(moved.end.0 - stroke.end.0, delta.0);
+            assert_eq!(moved.end.1 - stroke.end.1, delta.1);
+        }
+
+        // The viewBox follows the drawing when it is not normalized
+        let view_box = |l_system: &LSystem<&str, &str>| {
+            let svg = l_system
+                .to_svg_string(&SvgOptions {
+                    tight_view_box: true,
+                    ..Default::default()
+                })
+                .unwrap();
+            let (_, view_box) = svg.split_once(r#"viewBox=""#).unwrap();
+            view_box
+                .split_once('"')
+                .unwrap()
+                .0
+                .split(' ')
+                .map(|value| Decimal::from_str_exact(value).unwrap())
+                .collect::<Vec<_>>()
+        };
+        let (view_box, moved_view_box) = (view_box(&l_system), view_box(&moved));
+        assert_eq!(moved_view_box[0] - view_box[0], delta.0);
+        assert_eq!(moved_view_box[1] - view_box[1], delta.1);
+    }
+
+    #[test]
+    fn render_many() {
+        let square = LSystem::<_, &str> {
+            axiom: "F+F+F+F",
+            variables_to_draw: HashSet::from_iter(['F']),
+            angle: Decimal::HALF_PI,
+            ..Default::default()
+        };
+        let line = LSystem {
+            axiom: "FF",
+            ..square.clone()
+        };
+        let mut actual = vec![];
+        lsys::render_many(
+            &[
+                (square, Placement::default()),
+                (
+                    line,
+                    Placement {
+                        translation: (Decimal::TWO, Decimal::ZERO),
+                        scale: Decimal::TWO,
+                    },
+                ),
+            ],
+            &SvgOptions {
+                unit_length: Some(Decimal::ONE),
+                ..Default::default()
+            },
+            &mut actual,
+        )
+        .unwrap();
+        let actual = String::from_utf8(actual).unwrap();
+
+        assert_eq!(actual.matches("<svg").count(), 1);
+        let groups = actual.split("<g>").skip(1).collect::<Vec<_>>();
+        assert_eq!(groups.len(), 2);
+        assert_eq!(actual.matches("</g>").count(), 2);
+        // Both share one bounding box, with the line scaled beside the square and reaching higher
+        assert_eq!(
+            path_points(groups[0]),
+            [(0, 4), (0, 3), (1, 3), (1, 4), (0, 4)]
+                .map(|(x, y)| (Decimal::from(x), Decimal::from(y)))
+        );
+        assert_eq!(
+            path_points(groups[1]),
+            [(2, 4), (2, 2), (2, 0)].map(|(x, y)| (Decimal::from(x), Decimal::from(y)))
+        );
+    }
+
+    #[test]
+    fn clockwise() {
+        let clockwise = LSystem::<_, &str> {
+            axiom: "F+F-FTF",
+            variables_to_draw: HashSet::from_iter(['F']),
+            angle: Decimal::HALF_PI,
+            turn_angles: HashMap::from_iter([('T', Decimal::PI / Decimal::from(3))]),
+            initial_heading: Decimal::ZERO,
+            ..Default::default()
+        };
+        let counterclockwise = LSystem {
+            clockwise: false,
+            ..clockwise.clone()
+        };
+
+        // Mirrored about the x axis, which the turtle starts out heading along
+        let (clockwise, counterclockwise) = (clockwise.strokes(), counterclockwise.strokes());
+        assert_eq!(clockwise.len(), counterclockwise.len());
+        assert!(clockwise
+            .iter()
+            .any(|stroke| !stroke.end.1.round_dp(10).is_zero()));
+        for (clockwise, counterclockwise) in clockwise.iter().zip(&counterclockwise) {
+            assert_eq!(clockwise.end.0, counterclockwise.end.0);
+            assert_eq!(clockwise.end.1, -counterclockwise.end.1);
+        }
+    }
+
+    #[test]
+    fn turn_around() {
+        let strokes = LSystem::<_, &str> {
+            axiom: "F|F",
+            variables_to_draw: HashSet::from_iter(['F']),
+            initial_heading: Decimal::QUARTER_PI,
+            ..Default::default()
+        }
+        .strokes();
+
+        // Retraces the first stroke back to the origin instead of mirroring it
+        assert_eq!(strokes.len(), 2);
+        assert_eq!(strokes[1].start, strokes[0].end);
+        assert_eq!(strokes[1].end.0.round_dp(10), Decimal::ZERO);
+        assert_eq!(strokes[1].end.1.round_dp(10), Decimal::ZERO);
+    }
+
+    #[test]
+    fn turn_angles() {
+        let strokes = LSystem::<_, &str> {
+            axiom: "F+F(F)F",
+            variables_to_draw: HashSet::from_iter(['F']),
+            angle: Decimal::HALF_PI,
+            turn_angles: HashMap::from_iter([
+                ('(', Decimal::QUARTER_PI),
+                (')', -Decimal::QUARTER_PI),
+            ]),
+            initial_heading: Decimal::ZERO,
+            ..Default::default()
+        }
+        .strokes();
+
+        let direction = |stroke: &Stroke| {
+            (
+                (stroke.end.0 - stroke.start.0).round_dp(5),
+                (stroke.end.1 - stroke.start.1).round_dp(5),
+            )
+        };
+        let diagonal = (Decimal::TWO.sqrt().unwrap() / Decimal::TWO).round_dp(5);
+        assert_eq!(
+            strokes.iter().map(direction).collect::<Vec<_>>(),
+            [
+                (Decimal::ONE, Decimal::ZERO),
+                (Decimal::ZERO, Decimal::ONE),
+                (-diagonal, diagonal),
+                (Decimal::ZERO, Decimal::ONE),
+            ]
+        );
+    }
+
+    #[test]
+    fn angle_jitter() {
+        let l_system = LSystem {
+            axiom: "F",
+            variables_to_draw: HashSet::from_iter(['F']),
+            angle: Decimal::from(25) / Decimal::from(180) * Decimal::PI,
+            iterations: 3,
+            rules: HashMap::from_iter([('F', "F[+F]F[-F]F")]),
+            ..Default::default()
+        };
+        let jittered = LSystem {
+            angle_jitter: Decimal::new(1, 1),
+            ..l_system.clone()
+        };
+        assert_eq!(jittered.strokes(), jittered.clone().strokes());
+        assert_ne!(jittered.strokes(), l_system.strokes());
+        assert_ne!(
+            jittered.strokes(),
+            LSystem {
+                seed: 1,
+                ..jittered.clone()
+            }
+            .strokes()
+        );
+
+        // Without jitter, the seed has no effect
+        assert_eq!(
+            LSystem {
+                seed: 1,
+                ..l_system.clone()
+            }
+            .strokes(),
+            l_system.strokes()
+        );
+    }
+
+    #[test]
+    fn strokes_with_rng() {
+        /// Always draws the smallest value, so every turn changes by `-angle_jitter`.
+        struct Zeros;
+
+        impl rand::RngCore for Zeros {
+            fn next_u32(&mut self) -> u32 {
+                0
+            }
+
+            fn next_u64(&mut self) -> u64 {
+                0
+            }
+
+            fn fill_bytes(&mut self, dest: &mut [u8]) {
+                dest.fill(0);
+            }
+
+            fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), rand::Error> {
+                dest.fill(0);
+                Ok(())
+            }
+        }
+
+        let l_system = LSystem::<_, &str> {
+            axiom: "F+F-F",
+            variables_to_draw: HashSet::from_iter(['F']),
+            angle: Decimal::HALF_PI,
+            angle_jitter: Decimal::HALF_PI,
+            ..Default::default()
+        };
+        let ends = l_system
+            .strokes_with_rng(&mut Zeros)
+            .iter()
+            .map(|stroke| (stroke.end.0.round_dp(10), stroke.end.1.round_dp(10)))
+            .collect::<Vec<_>>();
+        // `+` no longer turns, and `-` turns twice as far
+        assert_eq!(
+            ends,
+            [
+                (Decimal::ZERO, -Decimal::ONE),
+                (Decimal::ZERO, -Decimal::TWO),
+                (Decimal::ZERO, -Decimal::ONE),
+            ]
+        );
+    }
+
+    #[test]
+    fn heading_at_end() {
+        let l_system = LSystem {
+            axiom: "F",
+            variables_to_draw: HashSet::from_iter(['F']),
+            angle: Decimal::QUARTER_PI,
+            iterations: 1,
+            rules: HashMap::from_iter([('F', "F[-F-F]+")]),
+            ..Default::default()
+        };
+        // Turns in the branch are undone when it ends, leaving the last one
+        assert_eq!(
+            l_system.heading_at_end(),
+            l_system.initial_heading + l_system.angle
+        );
+    }
+
+    #[test]
+    fn leaf_positions() {
+        let l_system = LSystem::<_, &str> {
+            axiom: "F[+F][-F[+F]]F[+]",
+            variables_to_draw: HashSet::from_iter(['F']),
+            angle: Decimal::HALF_PI,
+            ..Default::default()
+        };
+        // The branch that ends in another branch and the one that only turns have no tip
+        assert_eq!(l_system.leaf_positions().len(), 2);
+
+        let plant = lsys::examples()
+            .iter()
+            .find(|example| example.name == "Plant")
+            .unwrap()
+            .l_system()
+            .unwrap();
+        let leaves = plant.leaf_positions().len();
+        assert!(leaves > 0);
+        assert!(leaves <= plant.symbols().filter(|&c| c == ']').count());
+    }
+
+    #[test]
+    fn leaf_markers() {
+        let l_system = LSystem {
+            axiom: "X",
+            variables_to_draw: HashSet::from_iter(['F']),
+            angle: Decimal::from(25) / Decimal::from(180) * Decimal::PI,
+            iterations: 3,
+            rules: HashMap::from_iter([('X', "F[+X][-X]FX"), ('F', "FF")]),
+            ..Default::default()
+        };
+        let leaves = l_system.leaf_positions().len();
+        assert!(leaves > 1);
+
+        let actual = l_system
+            .to_svg_string(&SvgOptions {
+                leaf_dot_radius: Some(Decimal::new(1, 2)),
+                leaf_marker: Some(r#"<rect width="1" height="1"/>"#.to_string()),
+                ..Default::default()
+            })
+            .unwrap();
+        assert_eq!(actual.matches("<circle").count(), leaves);
+        assert_eq!(actual.matches("<rect").count(), leaves);
+
+        // Dots are placed in the normalized drawing, at the ends of its lines
+        let (_, d) = actual.split_once(r#" d=""#).unwrap();
+        let (d, _) = d.split_once('"').unwrap();
+        for circle in actual.split("<circle").skip(1) {
+            let attribute = |name: &str| {
+                let (_, value) = circle.split_once(&format!(r#"{name}=""#)).unwrap();
+                value.split_once('"').unwrap().0.to_string()
+            };
+            assert!(d.contains(&format!("L {} {}", attribute("cx"), attribute("cy"))));
+        }
+    }
+
+    #[test]
+    fn dots() {
+        let l_system = LSystem::<_, &str> {
+            axiom: ".F.F.",
+            variables_to_draw: HashSet::from_iter(['F']),
+            angle: Decimal::HALF_PI,
+            ..Default::default()
+        };
+        let actual = l_system
+            .to_svg_string(&SvgOptions {
+                unit_length: Some(Decimal::TEN),
+                dot_radius: Some(Decimal::TWO),
+                ..Default::default()
+            })
+            .unwrap();
+        let circles = actual
+            .lines()
+            .filter(|line| line.starts_with("<circle"))
+            .collect::<Vec<_>>();
+        assert_eq!(circles.len(), 3);
+        for (circle, cy) in circles.iter().zip(["20", "10", "0"]) {
+            assert!(circle.contains(&format!(r#"cy="{cy}""#)), "{circle}");
+            assert!(circle.contains(r#"r="2""#), "{circle}");
+        }
+        // Dots do not move the turtle
+        assert_eq!(l_system.strokes().len(), 2);
+
+        // Dots are colored like the line they sit on
+        let actual = l_system
+            .to_svg_string(&SvgOptions {
+                variable_colors: HashMap::from_iter([('F', "red".to_string())]),
+                ..Default::default()
+            })
+            .unwrap();
+        let fills = actual
+            .lines()
+            .filter(|line| line.starts_with("<circle"))
+            .map(|line| line.split(r#"fill=""#).nth(1).unwrap().split('"').next())
+            .collect::<Vec<_>>();
+        assert_eq!(fills, [Some("rgb(0%, 0%, 0%)"), Some("red"), Some("red")]);
+
+        // Dots are drawn without any lines
+        let actual = LSystem::<_, &str> {
+            axiom: ".",
+            ..Default::default()
+        }
+        .to_svg_string(&SvgOptions::default())
+        .unwrap();
+        assert_eq!(actual.matches("<circle").count(), 1);
+    }
+
+    #[test]
+    fn reset_heading() {
+        let strokes = LSystem::<_, &str> {
+            axiom: "F+F+F@F",
+            variables_to_draw: HashSet::from_iter(['F']),
+            angle: Decimal::HALF_PI,
+            initial_heading: Decimal::ZERO,
+            ..Default::default()
+        }
+        .strokes();
+
+        // Heading right again after turning around
+        let last = strokes.last().unwrap();
+        assert_eq!((last.end.0 - last.start.0).round_dp(10), Decimal::ONE);
+        assert_eq!((last.end.1 - last.start.1).round_dp(10), Decimal::ZERO);
+    }
+
+    #[test]
+    fn stats() {
+        let plant = LSystem {
+            axiom: "X",
+            variables_to_draw: HashSet::from_iter(['F']),
+            angle: Decimal::PI * Decimal::from_u32(25).unwrap() / Decimal::from_u32(180).unwrap(),
+            iterations: 1,
+            rules: lsys::parse_rules("X=>F-[[X]+X]+F[+FX]-X\nF=>FF").unwrap(),
+            ..Default::default()
+        };
+        let stats = plant.stats();
+        assert_eq!(stats.state_len, 18);
+        assert_eq!(stats.draw_count, 3);
+        assert_eq!(stats.branch_count, 3);
+        assert_eq!(stats.max_stack_depth, 2);
+
+        let stats = LSystem {
+            iterations: 2,
+            ..plant
+        }
+        .stats();
+        assert_eq!(stats.state_len, 89);
+        assert_eq!(stats.draw_count, 18);
+        assert_eq!(stats.branch_count, 15);
+        assert_eq!(stats.max_stack_depth, 4);
+        assert!(stats.extents.0 > Decimal::ZERO);
+        assert!(stats.extents.1 > Decimal::ZERO);
+    }
+
+    #[test]
+    fn draw_count() {
+        let koch = LSystem {
+            axiom: "F",
+            variables_to_draw: HashSet::from_iter(['F']),
+            angle: Decimal::HALF_PI,
+            iterations: 1,
+            rules: HashMap::from_iter([('F', "F+F-F-F+F")]),
+            ..Default::default()
+        };
+        assert_eq!(koch.draw_count(), 5);
+        for iterations in 0..5 {
+            let koch = LSystem {
+                iterations,
+                ..koch.clone()
+            };
+            assert_eq!(koch.draw_count(), koch.stats().draw_count);
+        }
+
+        let plant = LSystem {
+            axiom: "X",
+            variables_to_draw: HashSet::from_iter(['F']),
+            iterations: 2,
+            rules: lsys::parse_rules("X=>F-[[X]+X]+F[+FX]-X\nF=>FF").unwrap(),
+            ..Default::default()
+        };
+        assert_eq!(plant.draw_count(), 18);
+    }
+
+    #[test]
+    fn bounding_box() {
+        let square = LSystem::<_, &str> {
+            axiom: "F+F+F+F",
+            variables_to_draw: HashSet::from_iter(['F']),
+            angle: Decimal::HALF_PI,
+            initial_heading: Decimal::ZERO,
+            ..Default::default()
+        };
+        let (min_x, min_y, max_x, max_y) = square.bounding_box().unwrap();
+        assert_eq!(
+            [min_x, min_y, max_x, max_y].map(|value| value.round_dp(10)),
+            [Decimal::ZERO, Decimal::ZERO, Decimal::ONE, Decimal::ONE]
+        );
+
+        assert_eq!(
+            LSystem::<_, &str> {
+                axiom: "+-",
+                ..Default::default()
+            }
+            .bounding_box(),
+            None
+        );
+    }
+
+    #[test]
+    fn dimensions() {
+        let hilbert = lsys::examples()
+            .iter()
+            .find(|example| example.name == "Hilbert")
+            .unwrap()
+            .l_system()
+            .unwrap();
+        let (width, height) = hilbert.dimensions();
+        assert_eq!(width.round_dp(10), height.round_dp(10));
+        assert_eq!(width.round_dp(10), Decimal::from(63));
+
+        let (width, height) = LSystem::<_, &str> {
+            axiom: "FF+F",
+            variables_to_draw: HashSet::from_iter(['F']),
+            angle: Decimal::HALF_PI,
+            initial_heading: Decimal::ZERO,
+            ..Default::default()
+        }
+        .dimensions();
+        assert_eq!(
+            (width.round_dp(10), height.round_dp(10)),
+            (Decimal::TWO, Decimal::ONE)
+        );
+
+        assert_eq!(
+            LSystem::<&str, &str>::default().dimensions(),
+            (Decimal::ZERO, Decimal::ZERO)
+        );
+    }
+
+    #[test]
+    fn path_length() {
+        let l_system = LSystem::<_, &str> {
+            axiom: "FFFF",
+            variables_to_draw: HashSet::from_iter(['F']),
+            ..Default::default()
+        };
+        assert_eq!(l_system.path_length().round_dp(10), Decimal::from(4));
+
+        // Moves and branches are not drawn
+        let l_system = LSystem::<_, &str> {
+            axiom: "F[+FF]fF",
+            variables_to_draw: HashSet::from_iter(['F']),
+            variables_to_move: HashSet::from_iter(['f']),
+            angle: Decimal::HALF_PI,
+            step_lengths: HashMap::from_iter([('F', Decimal::TWO)]),
+            ..Default::default()
+        };
+        assert_eq!(l_system.path_length().round_dp(10), Decimal::from(8));
+    }
+
+    #[test]
+    fn sample_along() {
+        let l_system = LSystem::<_, &str> {
+            axiom: "FFFF",
+            variables_to_draw: HashSet::from_iter(['F']),
+            initial_heading: Decimal::ZERO,
+            ..Default::default()
+        };
+        let samples = l_system.sample_along(Decimal::ONE);
+        assert_eq!(
+            samples
+                .iter()
+                .map(|(x, y, _)| (x.round_dp(10), y.round_dp(10)))
+                .collect::<Vec<_>>(),
+            (0..=4)
+                .map(|x| (Decimal::from(x), Decimal::ZERO))
+                .collect::<Vec<_>>()
+        );
+        assert!(samples.iter().all(|(_, _, heading)| heading.is_zero()));
+
+        // Halfway along each line, pointing up
+        let samples = LSystem::<_, &str> {
+            initial_heading: -Decimal::HALF_PI,
+            ..l_system
+        }
+        .sample_along(Decimal::new(5, 1));
+        assert_eq!(samples.len(), 9);
+        assert_eq!(samples[3].1.round_dp(10), Decimal::new(-15, 1));
+        assert_eq!(samples[3].2.round_dp(10), (-Decimal::HALF_PI).round_dp(10));
+    }
+
+    #[test]
+    fn step_lengths() {
+        let l_system = LSystem::<_, &str> {
+            axiom: "F+G",
+            variables_to_draw: HashSet::from_iter(['F', 'G']),
+            angle: Decimal::HALF_PI,
+            initial_heading: Decimal::ZERO,
+            ..Default::default()
+        };
+        let unit = l_system.strokes();
+        let half = LSystem {
+            step_lengths: HashMap::from_iter([('G', Decimal::from_str_exact("0.5").unwrap())]),
+            ..l_system
+        }
+        .strokes();
+
+        assert_eq!(unit[0], half[0]);
+        let length = |stroke: &Stroke| (stroke.end.1 - stroke.start.1).round_dp(10);
+        assert_eq!(length(&unit[1]), Decimal::ONE);
+        assert_eq!(length(&half[1]), Decimal::from_str_exact("0.5").unwrap());
+    }
+
+    #[test]
+    fn variables_to_move() {
+        let l_system = LSystem::<_, &str> {
+            axiom: "FfF",
+            variables_to_draw: HashSet::from_iter(['F']),
+            variables_to_move: HashSet::from_iter(['f']),
+            ..Default::default()
+        };
+        assert_eq!(
+            l_system
+                .strokes()
+                .iter()
+                .map(|stroke| stroke.is_move)
+                .collect::<Vec<_>>(),
+            [false, true, false]
+        );
+
+        let mut actual = vec![];
+        l_system
+            .to_svg(&SvgOptions::default(), &mut actual)
+            .unwrap();
+        let actual = String::from_utf8(actual).unwrap();
+        let (_, path) = actual.split_once(" d=\"").unwrap();
+        let (path, _) = path.split_once('"').unwrap();
+        let commands = path
+            .split(' ')
+            .filter(|s| matches!(*s, "M" | "L"))
+            .collect::<Vec<_>>();
+        assert_eq!(commands[commands.len() - 2..], ["M", "L"]);
+    }
+
+    #[test]
+    fn draw_all() {
+        let l_system = LSystem::<_, &str> {
+            axiom: "AB",
+            draw_all: true,
+            ..Default::default()
+        };
+        let strokes = l_system.strokes();
+        assert_eq!(strokes.len(), 2);
+        assert!(strokes.iter().all(|stroke| !stroke.is_move));
+        assert_eq!(l_system.draw_count(), 2);
+        assert_eq!(
+            path_points(&l_system.to_svg_string(&SvgOptions::default()).unwrap()).len(),
+            3
+        );
+
+        // Operators, variables to move and turns are not drawn
+        let l_system = LSystem {
+            axiom: "A+fBT",
+            variables_to_move: HashSet::from_iter(['f']),
+            turn_angles: HashMap::from_iter([('T', Decimal::HALF_PI)]),
+            ..l_system
+        };
+        assert_eq!(
+            l_system
+                .strokes()
+                .iter()
+                .map(|stroke| stroke.is_move)
+                .collect::<Vec<_>>(),
+            [false, true, false]
+        );
+
+        let l_system = LSystem::builder()
+            .axiom("AB")
+            .rule('A', "AB")
+            .rule('B', "B")
+            .draw_all()
+            .iterations(1)
+            .build()
+            .unwrap();
+        assert_eq!(l_system.strokes().len(), 3);
+    }
+
+    /// SVGs are written by hand, so they render without the `cairo` feature.
+    #[cfg(not(feature = "cairo"))]
+    #[test]
+    fn svg_without_cairo() {
+        let actual = LSystem {
+            axiom: "F",
+            variables_to_draw: HashSet::from_iter(['F']),
+            angle: Decimal::HALF_PI,
+            iterations: 2,
+            rules: HashMap::from_iter([('F', "F+F-F-F+F")]),
+            ..Default::default()
+        }
+        .to_svg_string(&SvgOptions::default())
+        .unwrap();
+
+        assert!(actual.contains("<svg "));
+        assert!(actual.trim_end().ends_with("</svg>"));
+        assert_eq!(path_points(&actual).len(), 26);
+    }
+
+    #[cfg(feature = "cairo")]
+    #[test]
+    fn png() {
+        let mut actual = vec![];
+        LSystem {
+            axiom: "F",
+            variables_to_draw: HashSet::from_iter(['F']),
+            angle: Decimal::HALF_PI,
+            iterations: 2,
+            rules: HashMap::from_iter([('F', "F+F-F-F+F")]),
+            ..Default::default()
+        }
+        .to_png(&SvgOptions::default(), &mut actual)
+        .unwrap();
+
+        assert!(actual.starts_with(b"\x89PNG\r\n\x1a\n"));
+    }
+
+    #[cfg(feature = "cairo")]
+    #[test]
+    fn draw_to_context() {
+        let surface = cairo::ImageSurface::create(cairo::Format::ARgb32, 200, 100).unwrap();
+        let context = cairo::Context::new(&surface).unwrap();
+        // Draw into the right half, leaving the left half blank
+        context.translate(100., 0.);
+        let matrix = context.matrix();
+        LSystem {
+            axiom: "F",
+            variables_to_draw: HashSet::from_iter(['F']),
+            angle: Decimal::HALF_PI,
+            iterations: 2,
+            rules: HashMap::from_iter([('F', "F+F-F-F+F")]),
+            ..Default::default()
+        }
+        .draw_to_context(&context, &SvgOptions::default())
+        .unwrap();
+        assert_eq!(context.matrix(), matrix);
+        drop(context);
+
+        let stride = surface.stride() as usize;
+        let data = surface.take_data().unwrap();
+        let drawn = |columns: std::ops::Range<usize>| {
+            data.chunks(stride).any(|row| {
+                row[columns.start * 4..columns.end * 4]
+                    .iter()
+                    .any(|&byte| byte != 0)
+            })
+        };
+        assert!(!drawn(0..100));
+        assert!(drawn(100..200));
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn serde_round_trip() {
+        let l_system = LSystem {
+            axiom: "F".to_string(),
+            variables_to_draw: HashSet::from_iter(['F']),
+            angle: Decimal::HALF_PI,
+            iterations: 4,
+            rules: HashMap::from_iter([('F', "F+F-F-F+F".to_string())]),
+            ..Default::default()
+        };
+        let json = serde_json::to_string(&l_system).unwrap();
+        assert_eq!(
+            serde_json::from_str::<LSystem<String, String>>(&json).unwrap(),
+            l_system
+        );
+
+        let options = SvgOptions::default();
+        let json = serde_json::to_string(&options).unwrap();
+        assert_eq!(serde_json::from_str::<SvgOptions>(&json).unwrap(), options);
+    }
+
+    #[test]
+    fn parse_rules() {
+        assert_eq!(
+            lsys::parse_rules("X=>F-[[X]+X]+F[+FX]-X\n\nF=>FF\n").unwrap(),
+            HashMap::from_iter([
+                ('X', "F-[[X]+X]+F[+FX]-X".to_string()),
+                ('F', "FF".to_string())
+            ])
+        );
+        assert_eq!(
+            lsys::parse_rules("F=>FF\nX=F").unwrap_err(),
+            ParseError::MissingArrow { line: 2 }
+        );
+        assert_eq!(
+            lsys::parse_rules("FX=>F").unwrap_err(),
+            ParseError::InvalidVariable { line: 1 }
+        );
+        assert_eq!(
+            lsys::parse_rules("=>F").unwrap_err(),
+            ParseError::InvalidVariable { line: 1 }
+        );
+        assert_eq!(
+            lsys::parse_rules("F=>FF\n\nX=>").unwrap_err(),
+            ParseError::EmptyReplacement { line: 3 }
+        );
+    }
+
+    #[test]
+    fn parse_rules_with_comments_and_whitespace() {
+        assert_eq!(
+            lsys::parse_rules(
+                "# Koch curve\n  F => F+F-F-F+F   # koch\n\tG=>G G\n    # indented comment\n"
+            )
+            .unwrap(),
+            HashMap::from_iter([('F', "F+F-F-F+F".to_string()), ('G', "GG".to_string())])
+        );
+        assert_eq!(
+            lsys::parse_rules("F=>FF\nX=>  # nothing").unwrap_err(),
+            ParseError::EmptyReplacement { line: 2 }
+        );
+        assert_eq!(
+            lsys::parse_rules("# F=>FF\nX # =>F").unwrap_err(),
+            ParseError::MissingArrow { line: 2 }
+        );
+        // # is only a comment at the start of a line or after whitespace
+        assert_eq!(
+            lsys::parse_rules("#=>F#F # grows\nF=>F#"),
+            Ok(HashMap::from_iter([
+                ('#', "F#F".to_string()),
+                ('F', "F#".to_string())
+            ]))
+        );
+    }
+
+    #[test]
+    fn max_state_len() {
+        let l_system = LSystem {
+            axiom: "F",
+            variables_to_draw: HashSet::from_iter(['F']),
+            iterations: 5,
+            rules: HashMap::from_iter([('F', "FF")]),
+            max_state_len: Some(10),
+            ..Default::default()
+        };
+        assert_eq!(
+            l_system.try_calculate_final_state(),
+            Err(LSystemError::StateTooLarge {
+                produced: 16,
+                limit: 10
+            })
+        );
+        assert!(matches!(
+            l_system.to_svg(&SvgOptions::default(), vec![]),
+            Err(RenderError::LSystem(LSystemError::StateTooLarge { .. }))
+        ));
+        // Methods that cannot fail ignore the limit
+        assert_eq!(l_system.calculate_final_state(), "F".repeat(32));
+        assert_eq!(l_system.step("FFFFFFFFFFFF"), "F".repeat(24));
+        assert_eq!(l_system.strokes().len(), 32);
+        assert_eq!(
+            LSystem {
+                iterations: 3,
+                ..l_system
+            }
+            .calculate_final_state(),
+            "FFFFFFFF"
+        );
+    }
+
+    #[test]
+    fn max_stack_depth() {
+        // Each iteration nests another branch
+        let l_system = LSystem {
+            axiom: "F",
+            variables_to_draw: HashSet::from_iter(['F']),
+            angle: Decimal::HALF_PI,
+            iterations: 5,
+            rules: HashMap::from_iter([('F', "F[+F]")]),
+            max_stack_depth: Some(3),
+            ..Default::default()
+        };
+        assert!(matches!(
+            l_system.to_svg(&SvgOptions::default(), vec![]),
+            Err(RenderError::MaxDepthExceeded(3))
+        ));
+        assert!(matches!(
+            l_system.clone().prepared().strokes(),
+            Err(RenderError::MaxDepthExceeded(3))
+        ));
+        // Methods that cannot fail ignore the limit
+        assert_eq!(l_system.strokes().len(), 63);
+        assert_eq!(l_system.stats().max_stack_depth, 5);
+
+        let l_system = LSystem {
+            iterations: 3,
+            ..l_system
+        };
+        assert_eq!(l_system.stats().max_stack_depth, 3);
+        assert!(l_system.to_svg(&SvgOptions::default(), vec![]).is_ok());
+    }
+
+    #[test]
+    fn position_overflow() {
+        let l_system = LSystem::<_, &str> {
+            axiom: "FF",
+            variables_to_draw: HashSet::from_iter(['F']),
+            step_lengths: HashMap::from_iter([('F', Decimal::MAX)]),
+            ..Default::default()
+        };
+        assert!(matches!(
+            l_system.to_svg_string(&SvgOptions::default()),
+            Err(RenderError::LSystem(LSystemError::PositionOverflow))
+        ));
+        // Methods that cannot fail skip the step instead
+        assert_eq!(l_system.strokes().len(), 1);
+    }
+
+    #[test]
+    fn zero_iterations() {
+        let l_system = LSystem {
+            axiom: "F+F",
+            variables_to_draw: HashSet::from_iter(['F']),
+            angle: Decimal::HALF_PI,
+            rules: HashMap::from_iter([('F', "F-F")]),
+            ..Default::default()
+        };
+        assert_eq!(l_system.calculate_final_state(), "F+F");
+        assert_eq!(l_system.strokes().len(), 2);
+        assert_eq!(
+            l_system
+                .to_svg_string(&SvgOptions::default())
+                .unwrap()
+                .matches(" L ")
+                .count(),
+            2
+        );
+
+        // Far too many iterations fail quickly instead of running out of memory
+        assert!(matches!(
+            LSystem {
+                iterations: usize::MAX,
+                max_state_len: Some(1 << 20),
+                ..l_system
+            }
+            .to_svg_string(&SvgOptions::default()),
+            Err(RenderError::LSystem(LSystemError::StateTooLarge { .. }))
+        ));
+    }
+
+    #[test]
+    fn symbols() {
+        for (axiom, rules, iterations) in [
+            ("F", &["F=>F+F-F-F+F"][..], 3),
+            ("FX", &["X=>X+YF+", "Y=>-FX-Y"], 8),
+            ("X", &["X=>F-[[X]+X]+F[+FX]-X", "F=>FF"], 4),
+            ("F+F+F+F", &["F=>FF+F++F+F"], 0),
+        ] {
+            let l_system = LSystem {
+                axiom,
+                rules: lsys::parse_rules(&rules.join("\n")).unwrap(),
+                iterations,
+                ..Default::default()
+            };
+            assert_eq!(
+                l_system.symbols().collect::<String>(),
+                l_system.calculate_final_state()
+            );
+        }
+    }
+
+    #[test]
+    fn context_rules() {
+        // A signal moving one step to the right per iteration
+        let signal = LSystem {
+            axiom: "baaaa",
+            rules: HashMap::from_iter([('b', "a")]),
+            context_rules: vec![ContextRule {
+                left: Some('b'),
+                variable: 'a',
+                right: None,
+                replacement: "b",
+            }],
+            ..Default::default()
+        };
+        for (iterations, expected) in ["baaaa", "abaaa", "aabaa", "aaaba", "aaaab", "aaaaa"]
+            .into_iter()
+            .enumerate()
+        {
+            let l_system = LSystem {
+                iterations,
+                ..signal.clone()
+            };
+            assert_eq!(l_system.calculate_final_state(), expected);
+            assert_eq!(l_system.symbols().collect::<String>(), expected);
+        }
+
+        // The signal propagates into branches and past them
+        assert_eq!(
+            LSystem {
+                axiom: "b[a]+a",
+                iterations: 1,
+                ..signal.clone()
+            }
+            .calculate_final_state(),
+            "a[b]+b"
+        );
+        // Right context skips branches
+        assert_eq!(
+            LSystem {
+                axiom: "a[a]b",
+                rules: HashMap::new(),
+                context_rules: vec![ContextRule {
+                    left: None,
+                    variable: 'a',
+                    right: Some('b'),
+                    replacement: "c",
+                }],
+                iterations: 1,
+                ..Default::default()
+            }
+            .calculate_final_state(),
+            "c[a]b"
+        );
+
+        // States are checked as they are rewritten
+        let growing = LSystem {
+            axiom: "bF",
+            variables_to_draw: HashSet::from_iter(['F']),
+            rules: HashMap::from_iter([('F', "FF")]),
+            iterations: 4,
+            max_state_len: Some(10),
+            ..signal
+        };
+        assert!(matches!(
+            growing.to_svg_string(&SvgOptions::default()),
+            Err(RenderError::LSystem(LSystemError::StateTooLarge {
+                produced: 17,
+                limit: 10
+            }))
+        ));
+        assert!(LSystem {
+            max_state_len: Some(17),
+            ..growing
+        }
+        .to_svg_string(&SvgOptions::default())
+        .is_ok());
+    }
+
+    #[test]
+    fn parametric_growing_segment() {
+        let l_system = ParametricLSystem {
+            axiom: parse_modules("F(1)").unwrap(),
+            rules: vec!["F(x) => F(x*2)".parse().unwrap()],
+            variables_to_draw: HashSet::from_iter(['F']),
+            iterations: 3,
+            ..Default::default()
+        };
+        assert_eq!(
+            l_system.calculate_final_state().unwrap(),
+            parse_modules("F(8)").unwrap()
+        );
+        let strokes = l_system.strokes().unwrap();
+        assert_eq!(strokes.len(), 1);
+        assert_eq!(strokes[0].start, (Decimal::ZERO, Decimal::ZERO));
+        assert_eq!(strokes[0].end.1.round_dp(7), Decimal::from(-8));
+    }
+
+    #[test]
+    fn tokenized() {
+        assert_eq!(
+            lsys::tokenized::tokenize("Internode [ +Leaf]Internode"),
+            ["Internode", "[", "+", "Leaf", "]", "Internode"]
+        );
+
+        let mut l_system = TokenizedLSystem {
+            axiom: vec!["Leaf".to_string()],
+            rules: lsys::tokenized::parse_rules(
+                "Leaf => Internode [+Leaf] [-Leaf]  # branch\nInternode => Internode Internode",
+            )
+            .unwrap(),
+            variables_to_draw: HashSet::from_iter(["Internode".to_string()]),
+            angle: Decimal::HALF_PI,
+            iterations: 2,
+            ..Default::default()
+        };
+        assert_eq!(
+            l_system.calculate_final_state().join(" "),
+            "Internode Internode [ + Internode [ + Leaf ] [ - Leaf ] ] [ - Internode [ + Leaf ] [ - Leaf ] ]"
+        );
+
+        let by_char = LSystem {
+            axiom: "L",
+            variables_to_draw: HashSet::from_iter(['I']),
+            angle: Decimal::HALF_PI,
+            iterations: 3,
+            rules: lsys::parse_rules("L=>I[+L][-L]\nI=>II").unwrap(),
+            ..Default::default()
+        };
+        l_system.iterations = 3;
+        let mut expected = by_char.strokes();
+        for stroke in &mut expected {
+            stroke.variable = None;
+        }
+        assert_eq!(l_system.strokes(), expected);
+
+        assert_eq!(
+            lsys::tokenized::parse_rules("Leaf Stem => Leaf").unwrap_err(),
+            ParseError::InvalidModule { line: 1 }
+        );
+        assert_eq!(
+            lsys::tokenized::parse_rules("Leaf => Stem\n+ => -").unwrap_err(),
+            ParseError::OperatorModule { line: 2 }
+        );
+        // Operators are not rewritten even if a rule was added by hand
+        let with_operator_rule = TokenizedLSystem {
+            axiom: vec!["+".to_string(), "Leaf".to_string()],
+            rules: HashMap::from_iter([
+                ("+".to_string(), vec!["-".to_string()]),
+                ("Leaf".to_string(), vec!["Stem".to_string()]),
+            ]),
+            iterations: 1,
+            ..Default::default()
+        };
+        assert_eq!(
+            with_operator_rule.calculate_final_state(),
+            vec!["+".to_string(), "Stem".to_string()]
+        );
+    }
+
+    #[test]
+    fn parametric_two_parameters() {
+        let mut l_system = ParametricLSystem {
+            axiom: parse_modules("B(2)A(4,4)").unwrap(),
+            rules: [
+                "A(x,y) : y <= 3 => A(x*2, x+y)",
+                "A(x,y) : y > 3 => B(x)A(x/y, 0)",
+                "B(x) : x < 1 => C",
+                "B(x) : x >= 1 => B(x-1)",
+            ]
+            .into_iter()
+            .map(|rule| rule.parse().unwrap())
+            .collect(),
+            ..Default::default()
+        };
+        for (iterations, expected) in [
+            "B(2)A(4,4)",
+            "B(1)B(4)A(1,0)",
+            "B(0)B(3)A(2,1)",
+            "CB(2)A(4,3)",
+            "CB(1)A(8,7)",
+        ]
+        .into_iter()
+        .enumerate()
+        {
+            l_system.iterations = iterations;
+            let state = l_system.calculate_final_state().unwrap();
+            assert_eq!(
+                state.iter().map(ToString::to_string).collect::<String>(),
+                expected
+            );
+        }
+
+        assert_eq!(
+            "A(x) => A(y)".parse::<ParametricRule>().map(|rule| {
+                ParametricLSystem {
+                    axiom: parse_modules("A(1)").unwrap(),
+                    rules: vec![rule],
+                    iterations: 1,
+                    ..Default::default()
+                }
+                .calculate_final_state()
+            }),
+            Ok(Err(ParametricError::UnknownParameter("y".to_string())))
+        );
+        assert_eq!(
+            "A(x) A(y)".parse::<ParametricRule>(),
+            Err(ParametricError::MissingArrow)
         );
+        assert!(matches!(
+            parse_modules("F(1"),
+            Err(ParametricError::Syntax { position: 3, .. })
+        ));
     }
 
     #[test]
@@ -0,0 +1,50 @@
+//! Reading colors for [`crate::SvgOptions::palette`] from palette files.
+
+use std::path::Path;
+
+/// Error type for [`load_gpl_palette`] and [`parse_gpl_palette`].
+#[derive(Debug, thiserror::Error)]
+pub enum PaletteError {
+    #[error("I/O error while reading palette: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("a GIMP palette must start with `GIMP Palette`")]
+    MissingHeader,
+    #[error("line {line}: expected red, green and blue values from 0 to 255")]
+    InvalidColor { line: usize },
+}
+
+/// Read the colors of a GIMP palette (`.gpl`) file, like [`parse_gpl_palette`].
+pub fn load_gpl_palette(path: impl AsRef<Path>) -> Result<Vec<String>, PaletteError> {
+    parse_gpl_palette(&std::fs::read_to_string(path)?)
+}
+
+/// Parse the colors of a GIMP palette as CSS `rgb(...)` colors, in the order they are listed.
+///
+/// After the `GIMP Palette` header, each line is a color of red, green and blue values, followed
+/// by an optional name. `Name:` and `Columns:` headers, comments (`#`) and blank lines are
+/// skipped.
+pub fn parse_gpl_palette(palette: &str) -> Result<Vec<String>, PaletteError> {
+    let mut lines = palette.lines().enumerate();
+    match lines.next() {
+        Some((_, header)) if header.trim() == "GIMP Palette" => {}
+        _ => return Err(PaletteError::MissingHeader),
+    }
+    lines
+        .filter(|(_, line)| {
+            let line = line.trim();
+            !line.is_empty()
+                && !line.starts_with('#')
+                && !line.starts_with("Name:")
+                && !line.starts_with("Columns:")
+        })
+        .map(|(i, line)| {
+            let mut channels = line.split_whitespace().map(str::parse::<u8>);
+            match (channels.next(), channels.next(), channels.next()) {
+                (Some(Ok(red)), Some(Ok(green)), Some(Ok(blue))) => {
+                    Ok(format!("rgb({red}, {green}, {blue})"))
+                }
+                _ => Err(PaletteError::InvalidColor { line: i + 1 }),
+            }
+        })
+        .collect()
+}
@@ -0,0 +1,572 @@
+//! Parametric L-systems, where each symbol carries numeric parameters.
+//!
+//! Rules match a symbol along with its parameters, optionally guarded by a condition, and compute
+//! the parameters of the replacement with arithmetic expressions:
+//!
+//! ```text
+//! A(x,y) : y <= 3 => A(x*2,x+y)
+//! ```
+
+use crate::{default_initial_heading, write_svg, RenderError, Stroke, SvgOptions, Turtle};
+use rust_decimal::Decimal;
+use rust_decimal::MathematicalOps;
+use std::collections::HashMap;
+use std::collections::HashSet;
+use std::fmt;
+use std::io::Write;
+use std::str::FromStr;
+
+/// Parameters for a parametric L-system.
+///
+/// The turtle interprets modules like [`crate::LSystem`] interprets characters, using the first
+/// parameter if there is one:
+///
+/// | Module | Meaning |
+/// |--------|---------|
+/// | `+(a)` | Turn by `a` radians, or by [`Self::angle`] without a parameter |
+/// | `-(a)` | Turn by `a` radians in the other direction |
+/// | `F(l)` | Move forward by `l`, or by 1 without a parameter, for variables to draw or move |
+///
+/// `|`, `@`, `[`, `]` and `'` behave the same as in [`crate::LSystem`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParametricLSystem {
+    /// Initial modules.
+    pub axiom: Vec<Module>,
+    /// Rules in order of precedence; the first one that matches a module is used.
+    ///
+    /// Modules without a matching rule are replaced with themselves.
+    pub rules: Vec<ParametricRule>,
+    /// Variables that should be treated as a stroke and drawn.
+    pub variables_to_draw: HashSet<char>,
+    /// Variables that move the turtle forward without drawing.
+    pub variables_to_move: HashSet<char>,
+    /// Angle to turn by for `+` and `-` without a parameter, in radians.
+    pub angle: Decimal,
+    /// Number of iterations to run the L-system for.
+    pub iterations: usize,
+    /// Heading of the turtle before it starts drawing, in radians.
+    pub initial_heading: Decimal,
+}
+
+impl Default for ParametricLSystem {
+    fn default() -> Self {
+        Self {
+            axiom: vec![],
+            rules: vec![],
+            variables_to_draw: HashSet::new(),
+            variables_to_move: HashSet::new(),
+            angle: Decimal::ZERO,
+            iterations: 0,
+            initial_heading: default_initial_heading(),
+        }
+    }
+}
+
+/// A symbol and its parameters, such as `F(1.5)`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Module {
+    pub symbol: char,
+    pub parameters: Vec<Decimal>,
+}
+
+impl fmt::Display for Module {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.symbol)?;
+        if let Some((first, rest)) = self.parameters.split_first() {
+            write!(f, "({first}")?;
+            for parameter in rest {
+                write!(f, ",{parameter}")?;
+            }
+            write!(f, ")")?;
+        }
+        Ok(())
+    }
+}
+
+/// Rule replacing a module, such as `A(x,y) : y <= 3 => A(x*2,x+y)`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParametricRule {
+    pub symbol: char,
+    /// Names bound to the parameters of the module, which must have exactly this many.
+    pub parameters: Vec<String>,
+    /// The rule only applies when this evaluates to a nonzero value.
+    pub condition: Option<Expr>,
+    /// Symbols of the replacement along with expressions for their parameters.
+    pub replacement: Vec<(char, Vec<Expr>)>,
+}
+
+/// Arithmetic expression over the parameters of a [`ParametricRule`].
+///
+/// Comparisons and logical operators evaluate to 1 when true and 0 when false.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Expr {
+    Number(Decimal),
+    Parameter(String),
+    Negate(Box<Expr>),
+    Binary(Box<Expr>, BinaryOp, Box<Expr>),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BinaryOp {
+    Add,
+    Subtract,
+    Multiply,
+    Divide,
+    Power,
+    Less,
+    LessOrEqual,
+    Greater,
+    GreaterOrEqual,
+    Equal,
+    NotEqual,
+    And,
+    Or,
+}
+
+/// Error type for parsing and running a [`ParametricLSystem`].
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum ParametricError {
+    #[error("expected {expected} at position {position} of `{input}`")]
+    Syntax {
+        input: String,
+        position: usize,
+        expected: &'static str,
+    },
+    #[error("each rule must contain =>")]
+    MissingArrow,
+    #[error("unknown parameter `{0}`")]
+    UnknownParameter(String),
+    #[error("division by zero")]
+    DivisionByZero,
+    #[error("arithmetic overflow")]
+    Overflow,
+}
+
+impl ParametricLSystem {
+    /// Runs the L-system, returning its final state.
+    pub fn calculate_final_state(&self) -> Result<Vec<Module>, ParametricError> {
+        let mut state = self.axiom.clone();
+        for _ in 0..self.iterations {
+            let mut next = Vec::with_capacity(state.len());
+            for module in &state {
+                self.rewrite(module, &mut next)?;
+            }
+            state = next;
+        }
+        Ok(state)
+    }
+
+    /// Push the replacement of `module` from the first matching rule onto `next`.
+    fn rewrite(&self, module: &Module, next: &mut Vec<Module>) -> Result<(), ParametricError> {
+        for rule in &self.rules {
+            if rule.symbol != module.symbol || rule.parameters.len() != module.parameters.len() {
+                continue;
+            }
+            let bindings = rule
+                .parameters
+                .iter()
+                .map(String::as_str)
+                .zip(module.parameters.iter().copied())
+                .collect();
+            if let Some(condition) = &rule.condition {
+                if condition.evaluate(&bindings)?.is_zero() {
+                    continue;
+                }
+            }
+            for (symbol, expressions) in &rule.replacement {
+                next.push(Module {
+                    symbol: *symbol,
+                    parameters: expressions
+                        .iter()
+                        .map(|expression| expression.evaluate(&bindings))
+                        .collect::<Result<_, _>>()?,
+                });
+            }
+            return Ok(());
+        }
+        next.push(module.clone());
+        Ok(())
+    }
+
+    /// Run the L-system and walk the turtle over its final state.
+    pub fn strokes(&self) -> Result<Vec<Stroke>, ParametricError> {
+        Ok(self.turtle()?.strokes)
+    }
+
+    /// Run the L-system and convert it into an SVG.
+    pub fn to_svg<W>(&self, options: &SvgOptions, writer: W) -> Result<(), RenderError>
+    where
+        W: Write,
+    {
+        let turtle = self.turtle()?;
+        write_svg(&turtle, None, options, writer)
+    }
+
+    /// Turtle after walking over the final state.
+    fn turtle(&self) -> Result<Turtle, ParametricError> {
+        let mut turtle = Turtle::new(self.initial_heading);
+        for module in self.calculate_final_state()? {
+            let parameter = module.parameters.first().copied();
+            match module.symbol {
+                '+' => turtle.turn(parameter.unwrap_or(self.angle)),
+                '-' => turtle.turn(-parameter.unwrap_or(self.angle)),
+                '|' => turtle.reverse(),
+                '@' => turtle.reset_heading(),
+                '\'' | ';' => turtle.next_color(),
+                '!' => turtle.decrement_width(),
+                '.' => turtle.dot(),
+                '[' => turtle.push(),
+                ']' => turtle.pop(),
+                other
+                    if self.variables_to_draw.contains(&other)
+                        || self.variables_to_move.contains(&other) =>
+                {
+                    turtle.forward(
+                        parameter.unwrap_or(Decimal::ONE),
+                        !self.variables_to_draw.contains(&other),
+                        Some(other),
+                    );
+                }
+                _ => {}
+            }
+        }
+        if turtle.overflowed {
+            return Err(ParametricError::Overflow);
+        }
+        Ok(turtle)
+    }
+}
+
+impl Expr {
+    /// Evaluate the expression with the given parameter values.
+    pub fn evaluate(&self, bindings: &HashMap<&str, Decimal>) -> Result<Decimal, ParametricError> {
+        match self {
+            Expr::Number(value) => Ok(*value),
+            Expr::Parameter(name) => bindings
+                .get(name.as_str())
+                .copied()
+                .ok_or_else(|| ParametricError::UnknownParameter(name.clone())),
+            Expr::Negate(operand) => Ok(-operand.evaluate(bindings)?),
+            Expr::Binary(lhs, op, rhs) => {
+                let lhs = lhs.evaluate(bindings)?;
+                let rhs = rhs.evaluate(bindings)?;
+                let truth = |value: bool| if value { Decimal::ONE } else { Decimal::ZERO };
+                match op {
+                    BinaryOp::Add => lhs.checked_add(rhs).ok_or(ParametricError::Overflow),
+                    BinaryOp::Subtract => lhs.checked_sub(rhs).ok_or(ParametricError::Overflow),
+                    BinaryOp::Multiply => lhs.checked_mul(rhs).ok_or(ParametricError::Overflow),
+                    BinaryOp::Divide if rhs.is_zero() => Err(ParametricError::DivisionByZero),
+                    BinaryOp::Divide => lhs.checked_div(rhs).ok_or(ParametricError::Overflow),
+                    BinaryOp::Power => lhs.checked_powd(rhs).ok_or(ParametricError::Overflow),
+                    BinaryOp::Less => Ok(truth(lhs < rhs)),
+                    BinaryOp::LessOrEqual => Ok(truth(lhs <= rhs)),
+                    BinaryOp::Greater => Ok(truth(lhs > rhs)),
+                    BinaryOp::GreaterOrEqual => Ok(truth(lhs >= rhs)),
+                    BinaryOp::Equal => Ok(truth(lhs == rhs)),
+                    BinaryOp::NotEqual => Ok(truth(lhs != rhs)),
+                    BinaryOp::And => Ok(truth(!lhs.is_zero() && !rhs.is_zero())),
+                    BinaryOp::Or => Ok(truth(!lhs.is_zero() || !rhs.is_zero())),
+                }
+            }
+        }
+    }
+}
+
+impl FromStr for Expr {
+    type Err = ParametricError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parser = Parser::new(s);
+        let expr = parser.expr()?;
+        parser.end()?;
+        Ok(expr)
+    }
+}
+
+impl FromStr for ParametricRule {
+    type Err = ParametricError;
+
+    /// Parse a rule like `A(x,y) : y <= 3 => A(x*2,x+y)`, where the condition is optional.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (lhs, rhs) = s.split_once("=>").ok_or(ParametricError::MissingArrow)?;
+        let (predecessor, condition) = match lhs.split_once(':') {
+            Some((predecessor, condition)) => (predecessor, Some(condition.parse()?)),
+            None => (lhs, None),
+        };
+
+        let mut parser = Parser::new(predecessor);
+        let symbol = parser.symbol()?;
+        let mut parameters = vec![];
+        if parser.eat('(') {
+            parameters.push(parser.identifier()?);
+            while parser.eat(',') {
+                parameters.push(parser.identifier()?);
+            }
+            parser.close()?;
+        }
+        parser.end()?;
+
+        let mut parser = Parser::new(rhs);
+        let replacement = parser.modules()?;
+
+        Ok(Self {
+            symbol,
+            parameters,
+            condition,
+            replacement,
+        })
+    }
+}
+
+/// Parse modules with constant parameters, such as `B(2)A(4,4)`.
+pub fn parse_modules(s: &str) -> Result<Vec<Module>, ParametricError> {
+    let bindings = HashMap::new();
+    Parser::new(s)
+        .modules()?
+        .into_iter()
+        .map(|(symbol, expressions)| {
+            Ok(Module {
+                symbol,
+                parameters: expressions
+                    .iter()
+                    .map(|expression| expression.evaluate(&bindings))
+                    .collect::<Result<_, _>>()?,
+            })
+        })
+        .collect()
+}
+
+/// Recursive descent parser for modules and expressions.
+///
+/// Whitespace is ignored everywhere.
+struct Parser<'a> {
+    input: &'a str,
+    chars: Vec<char>,
+    position: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn new(input: &'a str) -> Self {
+        Self {
+            input,
+            chars: input.chars().collect(),
+            position: 0,
+        }
+    }
+
+    fn error(&self, expected: &'static str) -> ParametricError {
+        ParametricError::Syntax {
+            input: self.input.to_string(),
+            position: self.position,
+            expected,
+        }
+    }
+
+    fn peek(&mut self) -> Option<char> {
+        while self
+            .chars
+            .get(self.position)
+            .is_some_and(|c| c.is_whitespace())
+        {
+            self.position += 1;
+        }
+        self.chars.get(self.position).copied()
+    }
+
+    fn at_end(&mut self) -> bool {
+        self.peek().is_none()
+    }
+
+    fn end(&mut self) -> Result<(), ParametricError> {
+        if self.at_end() {
+            Ok(())
+        } else {
+            Err(self.error("end of input"))
+        }
+    }
+
+    fn eat(&mut self, c: char) -> bool {
+        let eaten = self.peek() == Some(c);
+        if eaten {
+            self.position += 1;
+        }
+        eaten
+    }
+
+    fn eat_str(&mut self, s: &str) -> bool {
+        self.peek();
+        let eaten = s
+            .chars()
+            .enumerate()
+            .all(|(i, c)| self.chars.get(self.position + i) == Some(&c));
+        if eaten {
+            self.position += s.chars().count();
+        }
+        eaten
+    }
+
+    fn close(&mut self) -> Result<(), ParametricError> {
+        if self.eat(')') {
+            Ok(())
+        } else {
+            Err(self.error("`)`"))
+        }
+    }
+
+    /// Symbols along with expressions for their parameters, until the end of input.
+    fn modules(&mut self) -> Result<Vec<(char, Vec<Expr>)>, ParametricError> {
+        let mut modules = vec![];
+        while !self.at_end() {
+            let symbol = self.symbol()?;
+            let mut expressions = vec![];
+            if self.eat('(') {
+                expressions.push(self.expr()?);
+                while self.eat(',') {
+                    expressions.push(self.expr()?);
+                }
+                self.close()?;
+            }
+            modules.push((symbol, expressions));
+        }
+        Ok(modules)
+    }
+
+    fn symbol(&mut self) -> Result<char, ParametricError> {
+        match self.peek() {
+            Some(c) if !matches!(c, '(' | ')' | ',') => {
+                self.position += 1;
+                Ok(c)
+            }
+            _ => Err(self.error("a symbol")),
+        }
+    }
+
+    fn identifier(&mut self) -> Result<String, ParametricError> {
+        match self.peek() {
+            Some(c) if c.is_alphabetic() || c == '_' => {
+                let start = self.position;
+                while self
+                    .chars
+                    .get(self.position)
+                    .is_some_and(|c| c.is_alphanumeric() || *c == '_')
+                {
+                    self.position += 1;
+                }
+                Ok(self.chars[start..self.position].iter().collect())
+            }
+            _ => Err(self.error("a parameter name")),
+        }
+    }
+
+    fn expr(&mut self) -> Result<Expr, ParametricError> {
+        let mut lhs = self.and()?;
+        while self.eat_str("||") {
+            lhs = Expr::Binary(Box::new(lhs), BinaryOp::Or, Box::new(self.and()?));
+        }
+        Ok(lhs)
+    }
+
+    fn and(&mut self) -> Result<Expr, ParametricError> {
+        let mut lhs = self.comparison()?;
+        while self.eat_str("&&") {
+            lhs = Expr::Binary(Box::new(lhs), BinaryOp::And, Box::new(self.comparison()?));
+        }
+        Ok(lhs)
+    }
+
+    fn comparison(&mut self) -> Result<Expr, ParametricError> {
+        let lhs = self.sum()?;
+        // Two-character operators must be tried first
+        let op = [
+            ("<=", BinaryOp::LessOrEqual),
+            (">=", BinaryOp::GreaterOrEqual),
+            ("==", BinaryOp::Equal),
+            ("!=", BinaryOp::NotEqual),
+            ("<", BinaryOp::Less),
+            (">", BinaryOp::Greater),
+        ]
+        .into_iter()
+        .find(|(token, _)| self.eat_str(token));
+        match op {
+            Some((_, op)) => Ok(Expr::Binary(Box::new(lhs), op, Box::new(self.sum()?))),
+            None => Ok(lhs),
+        }
+    }
+
+    fn sum(&mut self) -> Result<Expr, ParametricError> {
+        let mut lhs = self.product()?;
+        loop {
+            let op = if self.eat('+') {
+                BinaryOp::Add
+            } else if self.eat('-') {
+                BinaryOp::Subtract
+            } else {
+                return Ok(lhs);
+            };
+            lhs = Expr::Binary(Box::new(lhs), op, Box::new(self.product()?));
+        }
+    }
+
+    fn product(&mut self) -> Result<Expr, ParametricError> {
+        let mut lhs = self.power()?;
+        loop {
+            let op = if self.eat('*') {
+                BinaryOp::Multiply
+            } else if self.eat('/') {
+                BinaryOp::Divide
+            } else {
+                return Ok(lhs);
+            };
+            lhs = Expr::Binary(Box::new(lhs), op, Box::new(self.power()?));
+        }
+    }
+
+    fn power(&mut self) -> Result<Expr, ParametricError> {
+        let base = self.unary()?;
+        if self.eat('^') {
+            // Right associative
+            Ok(Expr::Binary(
+                Box::new(base),
+                BinaryOp::Power,
+                Box::new(self.power()?),
+            ))
+        } else {
+            Ok(base)
+        }
+    }
+
+    fn unary(&mut self) -> Result<Expr, ParametricError> {
+        if self.eat('-') {
+            Ok(Expr::Negate(Box::new(self.unary()?)))
+        } else {
+            self.primary()
+        }
+    }
+
+    fn primary(&mut self) -> Result<Expr, ParametricError> {
+        match self.peek() {
+            Some('(') => {
+                self.position += 1;
+                let expr = self.expr()?;
+                self.close()?;
+                Ok(expr)
+            }
+            Some(c) if c.is_ascii_digit() || c == '.' => {
+                let mut end = self.position;
+                while self
+                    .chars
+                    .get(end)
+                    .is_some_and(|c| c.is_ascii_digit() || *c == '.')
+                {
+                    end += 1;
+                }
+                let number: String = self.chars[self.position..end].iter().collect();
+                let value = Decimal::from_str(&number).map_err(|_| self.error("a number"))?;
+                self.position = end;
+                Ok(Expr::Number(value))
+            }
+            Some(c) if c.is_alphabetic() || c == '_' => Ok(Expr::Parameter(self.identifier()?)),
+            _ => Err(self.error("an expression")),
+        }
+    }
+}
@@ -0,0 +1,128 @@
+//! Output for pen plotters.
+
+use crate::{bounding_box, nonzero, pen_down_runs, LSystem, RenderError, Stroke};
+use rust_decimal::Decimal;
+use std::io::Write;
+
+/// Options to control output for pen plotters with [`LSystem::to_gcode`] and
+/// [`LSystem::to_hpgl`].
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(default))]
+pub struct PlotterOptions {
+    /// Width of the plotting area in millimeters.
+    pub width: Decimal,
+    /// Height of the plotting area in millimeters.
+    pub height: Decimal,
+    /// G-code that lifts the pen off the paper.
+    pub pen_up: String,
+    /// G-code that puts the pen on the paper.
+    pub pen_down: String,
+    /// Speed of drawn lines in millimeters per minute, if the plotter needs one.
+    pub feed_rate: Option<Decimal>,
+}
+
+impl Default for PlotterOptions {
+    /// A 100mm x 100mm area, moving the pen along the Z axis.
+    fn default() -> Self {
+        Self {
+            width: Decimal::ONE_HUNDRED,
+            height: Decimal::ONE_HUNDRED,
+            pen_up: "G0 Z1".to_string(),
+            pen_down: "G1 Z0".to_string(),
+            feed_rate: None,
+        }
+    }
+}
+
+impl<A, R> LSystem<A, R>
+where
+    A: AsRef<str>,
+    R: AsRef<str>,
+{
+    /// Run the L-system and convert it into G-code for a pen plotter.
+    ///
+    /// The drawing is scaled uniformly to fit the plotting area, with its origin in the bottom
+    /// left corner. Moves of the turtle are travel moves with the pen up.
+    pub fn to_gcode<W>(&self, options: &PlotterOptions, mut writer: W) -> Result<(), RenderError>
+    where
+        W: Write,
+    {
+        let lines = plot_lines(&self.walk(self.checked_symbols()?)?, options);
+
+        writeln!(writer, "G21 ; millimeters")?;
+        writeln!(writer, "G90 ; absolute positioning")?;
+        writeln!(writer, "{}", options.pen_up)?;
+        let feed_rate = options
+            .feed_rate
+            .map(|feed_rate| format!(" F{feed_rate}"))
+            .unwrap_or_default();
+        for line in &lines {
+            let (x, y) = line[0];
+            writeln!(writer, "G0 X{x} Y{y}")?;
+            writeln!(writer, "{}", options.pen_down)?;
+            for (x, y) in &line[1..] {
+                writeln!(writer, "G1 X{x} Y{y}{feed_rate}")?;
+            }
+            writeln!(writer, "{}", options.pen_up)?;
+        }
+        writeln!(writer, "G0 X0 Y0")?;
+
+        Ok(())
+    }
+
+    /// Run the L-system and convert it into HPGL for a pen plotter.
+    ///
+    /// The drawing is placed like [`Self::to_gcode`], in plotter units of 0.025mm.
+    pub fn to_hpgl<W>(&self, options: &PlotterOptions, mut writer: W) -> Result<(), RenderError>
+    where
+        W: Write,
+    {
+        let lines = plot_lines(&self.walk(self.checked_symbols()?)?, options);
+
+        writeln!(writer, "IN;")?;
+        writeln!(writer, "SP1;")?;
+        let plotter_units = |(x, y): (Decimal, Decimal)| {
+            let units_per_mm = Decimal::from(40);
+            ((x * units_per_mm).round(), (y * units_per_mm).round())
+        };
+        for line in &lines {
+            let (x, y) = plotter_units(line[0]);
+            writeln!(writer, "PU;")?;
+            writeln!(writer, "PA{x},{y};")?;
+            writeln!(writer, "PD;")?;
+            for &point in &line[1..] {
+                let (x, y) = plotter_units(point);
+                writeln!(writer, "PA{x},{y};")?;
+            }
+        }
+        writeln!(writer, "PU;")?;
+        writeln!(writer, "SP0;")?;
+
+        Ok(())
+    }
+}
+
+/// Connected runs of drawn strokes in millimeters, with the y axis pointing up.
+fn plot_lines(strokes: &[Stroke], options: &PlotterOptions) -> Vec<Vec<(Decimal, Decimal)>> {
+    let drawn = strokes
+        .iter()
+        .filter(|stroke| !stroke.is_move)
+        .copied()
+        .collect::<Vec<_>>();
+    let Some((min_x, min_y, max_x, max_y)) = bounding_box(&drawn) else {
+        return vec![];
+    };
+    let scale =
+        (options.width / nonzero(max_x - min_x)).min(options.height / nonzero(max_y - min_y));
+    let plot = |(x, y): (Decimal, Decimal)| {
+        (
+            ((x - min_x) * scale).round_dp(3).normalize(),
+            ((max_y - y) * scale).round_dp(3).normalize(),
+        )
+    };
+    pen_down_runs(strokes, |_| true)
+        .into_iter()
+        .map(|(line, _)| line.into_iter().map(plot).collect())
+        .collect()
+}
@@ -0,0 +1,151 @@
+//! Drawing an L-system repeatedly without rerunning its rules.
+
+use crate::{
+    write_svg, ContextRule, LSystem, LSystemError, RenderError, Stroke, SvgOptions, Turtle,
+};
+use std::collections::HashMap;
+use std::io::Write;
+
+/// An L-system that keeps its final state between renders, from [`LSystem::prepared`].
+///
+/// The rules only run again when the axiom, rules, iterations or maximum state length of
+/// [`Self::l_system`] change, so redrawing with a new angle or [`SvgOptions`] only walks the
+/// turtle.
+#[derive(Debug, Clone)]
+pub struct Prepared<A: AsRef<str>, R: AsRef<str>> {
+    /// L-system to draw, which can be changed between renders.
+    pub l_system: LSystem<A, R>,
+    expanded: Option<Expanded>,
+}
+
+/// Final state of an L-system, along with what it was calculated from.
+#[derive(Debug, Clone)]
+struct Expanded {
+    axiom: String,
+    rules: HashMap<char, String>,
+    context_rules: Vec<ContextRule<String>>,
+    iterations: usize,
+    max_state_len: Option<usize>,
+    final_state: String,
+}
+
+impl Expanded {
+    /// Whether `l_system` has the same final state.
+    fn is_for<A, R>(&self, l_system: &LSystem<A, R>) -> bool
+    where
+        A: AsRef<str>,
+        R: AsRef<str>,
+    {
+        self.axiom == l_system.axiom.as_ref()
+            && self.iterations == l_system.iterations
+            && self.max_state_len == l_system.max_state_len
+            && self.rules.len() == l_system.rules.len()
+            && l_system.rules.iter().all(|(variable, replacement)| {
+                self.rules.get(variable).map(String::as_str) == Some(replacement.as_ref())
+            })
+            && self.context_rules.len() == l_system.context_rules.len()
+            && self
+                .context_rules
+                .iter()
+                .zip(&l_system.context_rules)
+                .all(|(expanded, rule)| {
+                    expanded.left == rule.left
+                        && expanded.variable == rule.variable
+                        && expanded.right == rule.right
+                        && expanded.replacement == rule.replacement.as_ref()
+                })
+    }
+
+    /// The `cached` final state if it is for `l_system`, or else its new one.
+    fn cached<'a, A, R>(
+        cached: &'a mut Option<Self>,
+        l_system: &LSystem<A, R>,
+    ) -> Result<&'a Self, LSystemError>
+    where
+        A: AsRef<str>,
+        R: AsRef<str>,
+    {
+        let expanded = match cached.take() {
+            Some(expanded) if expanded.is_for(l_system) => expanded,
+            _ => Self {
+                axiom: l_system.axiom.as_ref().to_string(),
+                rules: l_system
+                    .rules
+                    .iter()
+                    .map(|(variable, replacement)| (*variable, replacement.as_ref().to_string()))
+                    .collect(),
+                context_rules: l_system
+                    .context_rules
+                    .iter()
+                    .map(|rule| ContextRule {
+                        left: rule.left,
+                        variable: rule.variable,
+                        right: rule.right,
+                        replacement: rule.replacement.as_ref().to_string(),
+                    })
+                    .collect(),
+                iterations: l_system.iterations,
+                max_state_len: l_system.max_state_len,
+                final_state: l_system.expand(false)?,
+            },
+        };
+        Ok(cached.insert(expanded))
+    }
+}
+
+impl<A, R> LSystem<A, R>
+where
+    A: AsRef<str>,
+    R: AsRef<str>,
+{
+    /// Keep the final state of the L-system between renders with [`Prepared`].
+    ///
+    /// The rules run on the first render rather than here.
+    pub fn prepared(self) -> Prepared<A, R> {
+        Prepared {
+            l_system: self,
+            expanded: None,
+        }
+    }
+}
+
+impl<A, R> Prepared<A, R>
+where
+    A: AsRef<str>,
+    R: AsRef<str>,
+{
+    /// Final state of [`Self::l_system`], only running the rules if it has changed.
+    ///
+    /// Fails with [`LSystemError::StateTooLarge`] like [`LSystem::try_calculate_final_state`],
+    /// but replaces letters without a rule with themselves.
+    pub fn final_state(&mut self) -> Result<&str, LSystemError> {
+        Ok(&Expanded::cached(&mut self.expanded, &self.l_system)?.final_state)
+    }
+
+    /// Walk the turtle over the final state, like [`LSystem::strokes`].
+    pub fn strokes(&mut self) -> Result<Vec<Stroke>, RenderError> {
+        Ok(self.turtle()?.strokes)
+    }
+
+    /// Convert the final state into an SVG, like [`LSystem::to_svg`].
+    pub fn to_svg<W>(&mut self, options: &SvgOptions, writer: W) -> Result<(), RenderError>
+    where
+        W: Write,
+    {
+        let turtle = self.turtle()?;
+        write_svg(&turtle, None, options, writer)
+    }
+
+    /// Turtle after walking over the final state.
+    fn turtle(&mut self) -> Result<Turtle, RenderError> {
+        let expanded = Expanded::cached(&mut self.expanded, &self.l_system)?;
+        self.l_system.walk_turtle(expanded.final_state.chars())
+    }
+
+    /// Convert the final state into an SVG, like [`LSystem::to_svg_string`].
+    pub fn to_svg_string(&mut self, options: &SvgOptions) -> Result<String, RenderError> {
+        let mut svg = vec![];
+        self.to_svg(options, &mut svg)?;
+        Ok(String::from_utf8(svg).expect("SVG is valid UTF-8"))
+    }
+}
@@ -0,0 +1,181 @@
+//! L-systems over modules with multi-character names, such as `Internode` or `Leaf`.
+//!
+//! Modules are separated by whitespace, and operators are always a single symbol, so these are
+//! the same:
+//!
+//! ```text
+//! Internode [ + Leaf ] Internode
+//! Internode[+Leaf]Internode
+//! ```
+
+use crate::{
+    default_initial_heading, is_operator, write_svg, ParseError, RenderError, Stroke, SvgOptions,
+    Turtle,
+};
+use rust_decimal::Decimal;
+use std::collections::HashMap;
+use std::collections::HashSet;
+use std::io::Write;
+
+/// Parameters for an L-system over named modules.
+///
+/// The turtle interprets operator modules like [`crate::LSystem`] interprets characters.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TokenizedLSystem {
+    /// Initial modules.
+    pub axiom: Vec<String>,
+    /// Rules for replacing a module with new modules.
+    ///
+    /// Modules without a rule are replaced with themselves. Operators are never replaced, even if
+    /// they have a rule.
+    pub rules: HashMap<String, Vec<String>>,
+    /// Modules that should be treated as a stroke and drawn.
+    pub variables_to_draw: HashSet<String>,
+    /// Modules that move the turtle forward without drawing.
+    pub variables_to_move: HashSet<String>,
+    /// Angle to turn by for `+` and `-`, in radians.
+    pub angle: Decimal,
+    /// Number of iterations to run the L-system for.
+    pub iterations: usize,
+    /// Heading of the turtle before it starts drawing, in radians.
+    pub initial_heading: Decimal,
+}
+
+impl Default for TokenizedLSystem {
+    fn default() -> Self {
+        Self {
+            axiom: vec![],
+            rules: HashMap::new(),
+            variables_to_draw: HashSet::new(),
+            variables_to_move: HashSet::new(),
+            angle: Decimal::ZERO,
+            iterations: 0,
+            initial_heading: default_initial_heading(),
+        }
+    }
+}
+
+impl TokenizedLSystem {
+    /// Runs the L-system, returning its final state.
+    pub fn calculate_final_state(&self) -> Vec<String> {
+        let mut state = self.axiom.clone();
+        for _ in 0..self.iterations {
+            state = state
+                .into_iter()
+                .flat_map(|module| match self.replacement(&module) {
+                    Some(replacement) => replacement.clone(),
+                    None => vec![module],
+                })
+                .collect();
+        }
+        state
+    }
+
+    /// Replacement for a module, if it has a rule and is not an operator.
+    fn replacement(&self, module: &str) -> Option<&Vec<String>> {
+        if is_operator_module(module) {
+            None
+        } else {
+            self.rules.get(module)
+        }
+    }
+
+    /// Run the L-system and walk the turtle over its final state.
+    ///
+    /// Modules have no [`Stroke::variable`], since it is a single character.
+    pub fn strokes(&self) -> Vec<Stroke> {
+        self.turtle().strokes
+    }
+
+    /// Run the L-system and convert it into an SVG.
+    pub fn to_svg<W>(&self, options: &SvgOptions, writer: W) -> Result<(), RenderError>
+    where
+        W: Write,
+    {
+        let turtle = self.turtle();
+        write_svg(&turtle, None, options, writer)
+    }
+
+    /// Turtle after walking over the final state.
+    fn turtle(&self) -> Turtle {
+        let mut turtle = Turtle::new(self.initial_heading);
+        for module in self.calculate_final_state() {
+            match module.as_str() {
+                "+" => turtle.turn(self.angle),
+                "-" => turtle.turn(-self.angle),
+                "|" => turtle.reverse(),
+                "@" => turtle.reset_heading(),
+                "'" | ";" => turtle.next_color(),
+                "!" => turtle.decrement_width(),
+                "." => turtle.dot(),
+                "[" => turtle.push(),
+                "]" => turtle.pop(),
+                other
+                    if self.variables_to_draw.contains(other)
+                        || self.variables_to_move.contains(other) =>
+                {
+                    turtle.forward(Decimal::ONE, !self.variables_to_draw.contains(other), None);
+                }
+                _ => {}
+            }
+        }
+        turtle
+    }
+}
+
+/// Split `s` into modules separated by whitespace, with each operator as its own module.
+pub fn tokenize(s: &str) -> Vec<String> {
+    let mut modules = vec![];
+    let mut module = String::new();
+    for c in s.chars() {
+        if c.is_whitespace() || is_operator(c) {
+            if !module.is_empty() {
+                modules.push(std::mem::take(&mut module));
+            }
+            if is_operator(c) {
+                modules.push(c.to_string());
+            }
+        } else {
+            module.push(c);
+        }
+    }
+    if !module.is_empty() {
+        modules.push(module);
+    }
+    modules
+}
+
+/// Whether a module is a single operator symbol.
+fn is_operator_module(module: &str) -> bool {
+    let mut chars = module.chars();
+    matches!((chars.next(), chars.next()), (Some(c), None) if is_operator(c))
+}
+
+/// Parse rules for replacing modules with new modules, one per line (i.e. `Leaf => Stem[+Leaf]`).
+///
+/// Comments and blank lines are handled like [`crate::parse_rules`]. Operators cannot be
+/// rewritten, so rules for them are rejected.
+pub fn parse_rules(rules: &str) -> Result<HashMap<String, Vec<String>>, ParseError> {
+    rules
+        .lines()
+        .map(crate::strip_comment)
+        .enumerate()
+        .filter(|(_, rule)| !rule.trim().is_empty())
+        .map(|(i, rule)| {
+            let line = i + 1;
+            let (module, replacement) = rule
+                .split_once("=>")
+                .ok_or(ParseError::MissingArrow { line })?;
+            let [module] = <[String; 1]>::try_from(tokenize(module))
+                .map_err(|_| ParseError::InvalidModule { line })?;
+            if is_operator_module(&module) {
+                return Err(ParseError::OperatorModule { line });
+            }
+            let replacement = tokenize(replacement);
+            if replacement.is_empty() {
+                return Err(ParseError::EmptyReplacement { line });
+            }
+            Ok((module, replacement))
+        })
+        .collect()
+}
@@ -1,15 +1,12 @@
 #![allow(non_snake_case)]
 
-use std::{
-    collections::{HashMap, HashSet},
-    iter::once,
-};
+use std::collections::{HashMap, HashSet};
 
 use base64::Engine;
 use dioxus::prelude::*;
 use dioxus_logger::tracing::Level;
-use lsys::{LSystem, SvgOptions};
-use rust_decimal::{prelude::FromPrimitive, Decimal};
+use lsys::{LSystem, Prepared, SvgOptions};
+use rust_decimal::Decimal;
 use svgtypes::LengthUnit;
 use wasm_bindgen::JsCast;
 use web_sys::window;
@@ -26,31 +23,21 @@ fn App() -> Element {
     let mut angle = use_signal(|| 90.to_string());
     let mut iterations = use_signal(|| 4.to_string());
     let mut svg: Signal<Option<String>> = use_signal(Option::default);
+    let mut render_error: Signal<Option<String>> = use_signal(Option::default);
+    let mut prepared: Signal<Option<Prepared<String, String>>> = use_signal(Option::default);
     let mut copied: Signal<bool> = use_signal(|| false);
     let mut examples_open: Signal<bool> = use_signal(|| false);
 
-    let rules_result: Result<HashMap<char, String>, &'static str> = rules
-        .read()
-        .lines()
-        .map(|r| {
-            let (c, replacement) = r.split_once("=>").ok_or("each rule must contain =>")?;
-            if c.chars().count() != 1 {
-                return Err("=> must be preceded by a single char");
-            }
-            if replacement.is_empty() {
-                return Err("=> must be followed by a replacement string");
-            }
-            Ok((c.chars().next().unwrap(), replacement.to_string()))
-        })
-        .collect::<Result<HashMap<_, _>, _>>()
+    let rules_result: Result<HashMap<char, String>, String> = lsys::parse_rules(&rules.read())
+        .map_err(|err| err.to_string())
         .and_then(|rules| {
             if !axiom
                 .read()
                 .chars()
-                .filter(|c| !matches!(c, '+' | '-' | '|' | '[' | ']'))
+                .filter(|c| !lsys::is_operator(*c))
                 .all(|c| rules.contains_key(&c))
             {
-                Err("missing rule")
+                Err("missing rule".to_string())
             } else {
                 Ok(rules)
             }
@@ -84,14 +71,16 @@ fn App() -> Element {
         angle_result.as_ref(),
         iterations_result.as_ref(),
     ) {
-        (Ok(rules), Ok(variables_to_draw), Ok(angle), Ok(iterations)) => Some(LSystem {
-            axiom: axiom.read().clone(),
-            rules: rules.clone(),
-            variables_to_draw: variables_to_draw.chars().collect(),
-            angle: angle.clone() / Decimal::from_usize(180).expect("180 is a decimal")
-                * Decimal::PI,
-            iterations: *iterations,
-        }),
+        (Ok(rules), Ok(variables_to_draw), Ok(angle), Ok(iterations)) => Some(
+            LSystem {
+                axiom: axiom.read().clone(),
+                rules: rules.clone(),
+                variables_to_draw: variables_to_draw.chars().collect(),
+                iterations: *iterations,
+                ..Default::default()
+            }
+            .with_angle_degrees(*angle),
+        ),
         _ => None,
     };
 
@@ -114,20 +103,34 @@ fn App() -> Element {
         main {
             form {
                 onsubmit: move |_| {
-                    let mut acc = vec![];
-                    l_system
-                        .as_ref()
-                        .expect("checked for errors")
-                        .to_svg(
-                            &SvgOptions {
-                                width: Decimal::try_from(500.).unwrap(),
-                                height: Decimal::try_from(500.).unwrap(),
-                                units: LengthUnit::Px,
-                            },
-                            &mut acc,
-                        )
-                        .unwrap();
-                    svg.set(Some(String::from_utf8(acc).unwrap()));
+                    let l_system = l_system.clone().expect("checked for errors");
+                    let mut prepared = prepared.write();
+                    let prepared = match prepared.as_mut() {
+                        Some(prepared) => {
+                            prepared.l_system = l_system;
+                            prepared
+                        }
+                        None => prepared.insert(l_system.prepared()),
+                    };
+                    let rendered = prepared
+                        .to_svg_string(&SvgOptions {
+                            width: Decimal::try_from(500.).unwrap(),
+                            height: Decimal::try_from(500.).unwrap(),
+                            units: LengthUnit::Px,
+                            adaptive_stroke_color: true,
+                            xml_declaration: false,
+                            ..Default::default()
+                        });
+                    match rendered {
+                        Ok(rendered) => {
+                            svg.set(Some(rendered));
+                            render_error.set(None);
+                        }
+                        Err(err) => {
+                            svg.set(None);
+                            render_error.set(Some(err.to_string()));
+                        }
+                    }
                     copied.set(false);
                 },
                 fieldset { class: "grid",
@@ -151,7 +154,7 @@ fn App() -> Element {
                             oninput: move |event| rules.set(event.value())
                         }
                         if let Err(err) = rules_result.as_ref() {
-                            small { id: "rules-helper", {err} }
+                            small { id: "rules-helper", {err.clone()} }
                         }
                     }
                     label {
@@ -218,11 +221,14 @@ fn App() -> Element {
                         "Examples"
                     }
                 }
+                if let Some(err) = render_error.read().as_ref() {
+                    small { id: "render-error", {err.clone()} }
+                }
             }
 
             if let Some(svg) = svg.read().as_ref() {
                 article {
-                    div { dangerous_inner_html: lsys_svg_to_color_scheme_aware_svg(svg) }
+                    div { dangerous_inner_html: "{svg}" }
                     footer {
                         div { class: "grid",
                             a { role: "button", onclick: copy_onclick,
@@ -278,7 +284,7 @@ fn App() -> Element {
                         p { "Example L-systems" }
                     }
                     div {
-                        for examples in EXAMPLES.chunks(3) {
+                        for examples in lsys::examples().chunks(3) {
                             div { class: "grid",
                                 for example in examples {
                                     a {
@@ -287,7 +293,7 @@ fn App() -> Element {
                                             axiom.set(example.axiom.to_string());
                                             rules.set(example.rules.join("\n"));
                                             variables_to_draw.set(example.variables_to_draw.to_string());
-                                            angle.set(example.angle.to_string());
+                                            angle.set(example.angle_degrees.to_string());
                                             iterations.set(example.iterations.to_string());
                                             examples_open.set(false);
                                         },
@@ -306,124 +312,3 @@ fn App() -> Element {
         }
     }
 }
-
-fn lsys_svg_to_color_scheme_aware_svg(svg: &str) -> String {
-    let style = "<style>\
-        @media (prefers-color-scheme: light) { path { stroke: black; } }\
-        @media (prefers-color-scheme: dark) { path { stroke: white; } }\
-    </style>";
-    svg.lines()
-        .take(2)
-        .chain(once(style))
-        .chain(svg.lines().skip(2))
-        .collect()
-}
-
-struct Example {
-    name: &'static str,
-    axiom: &'static str,
-    variables_to_draw: &'static str,
-    rules: &'static [&'static str],
-    angle: f64,
-    iterations: usize,
-}
-const EXAMPLES: &[Example] = &[
-    Example {
-        name: "Koch",
-        axiom: "F",
-        variables_to_draw: "F",
-        rules: &["F=>F+F-F-F+F"],
-        angle: 90.,
-        iterations: 4,
-    },
-    Example {
-        name: "Sierpinski Triangle",
-        axiom: "F-G-G",
-        variables_to_draw: "FG",
-        rules: &["F=>F-G+F+G-F", "G=>GG"],
-        angle: 120.,
-        iterations: 6,
-    },
-    Example {
-        name: "Sierpinski Arrowhead",
-        axiom: "A",
-        variables_to_draw: "AB",
-        rules: &["A=>B-A-B", "B=>A+B+A"],
-        angle: 60.,
-        iterations: 7,
-    },
-    Example {
-        name: "Dragon",
-        axiom: "FX",
-        variables_to_draw: "F",
-        rules: &["X=>X+YF+", "Y=>-FX-Y", "F=>F"],
-        angle: 90.,
-        iterations: 4,
-    },
-    Example {
-        name: "Plant",
-        axiom: "X",
-        variables_to_draw: "F",
-        rules: &["X=>F-[[X]+X]+F[+FX]-X", "F=>FF"],
-        angle: 25.,
-        iterations: 5,
-    },
-    Example {
-        name: "Moore",
-        axiom: "LFL+F+LFL",
-        variables_to_draw: "F",
-        rules: &["L=>-RF+LFL+FR-", "R=>+LF-RFR-FL+", "F=>F"],
-        angle: 90.,
-        iterations: 5,
-    },
-    Example {
-        name: "Hilbert",
-        axiom: "A",
-        variables_to_draw: "F",
-        rules: &["A=>-BF+AFA+FB-", "B=>+AF-BFB-FA+", "F=>F"],
-        angle: 90.,
-        iterations: 6,
-    },
-    Example {
-        name: "Sierpinski Carpet",
-        axiom: "F+F+F+F",
-        variables_to_draw: "F",
-        rules: &["F=>FF+F+F+F+FF"],
-        angle: 90.,
-        iterations: 4,
-    },
-    Example {
-        name: "Snowflake",
-        axiom: "F++F++F",
-        variables_to_draw: "F",
-        rules: &["F=>F-F++F-F"],
-        angle: 60.,
-        iterations: 4,
-    },
-    Example {
-        name: "Gosper",
-        axiom: "XF",
-        variables_to_draw: "F",
-        rules: &[
-            "X=>X+YF++YF-FX--FXFX-YF+",
-            "Y=>-FX+YFYF++YF+FX--FX-Y",
-            "F=>F",
-        ],
-        angle: 60.,
-        iterations: 5,
-    },
-    Example {
-        name: "Kolam",
-        axiom: "-D--D",
-        variables_to_draw: "F",
-        rules: &[
-            "A=>F++FFFF--F--FFFF++F++FFFF--F",
-            "B=>F--FFFF++F++FFFF--F--FFFF++F",
-            "C=>BFA--BFA",
-            "D=>CFC--CFC",
-            "F=>F",
-        ],
-        angle: 45.,
-        iterations: 7,
-    },
-];